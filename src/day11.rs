@@ -1,23 +1,18 @@
 const INPUT : &'static str = include_str!("../inputs/day11.txt");
 
 use std::collections::HashMap;
-use crate::intcode::{Program, Computer, InvalidInstruction, IOEvent};
+use crate::intcode::{Program, Machine, InvalidInstruction};
+use crate::ocr::{Grid,OcrError};
 
 type Point = (isize, isize);
 
 const WHITE : isize = 1;
 const BLACK : isize = 0;
 
-enum RoboState {
-    Paint,
-    Turn
-}
-
 struct Robot {
     grid: HashMap<Point, isize>,
     pos:  Point,
-    facing: Point,
-    state: RoboState
+    facing: Point
 }
 
 impl Robot {
@@ -25,33 +20,79 @@ impl Robot {
         Robot {
             grid: HashMap::new(),
             pos: (0, 0),
-            facing: (0, -1),
-            state: RoboState::Paint
+            facing: (0, -1)
         }
     }
 
-    pub fn print(&self) {
+    // The painted area as rows of white cells, including any black border.
+    pub fn pixels(&self) -> Vec<Vec<bool>> {
+        self.cells().iter().map(|row| row.iter().map(|&v| v == Some(WHITE)).collect()).collect()
+    }
+
+    // Every cell in the bounding box of the painted area, `None` if unpainted.
+    fn cells(&self) -> Vec<Vec<Option<isize>>> {
+        if self.grid.is_empty() {
+            return vec![];
+        }
+
         let min_x = self.grid.keys().min_by_key(|p| p.0).unwrap().0;
         let max_x = self.grid.keys().max_by_key(|p| p.0).unwrap().0;
         let min_y = self.grid.keys().min_by_key(|p| p.1).unwrap().1;
         let max_y = self.grid.keys().max_by_key(|p| p.1).unwrap().1;
 
-        for y in min_y..(max_y + 1) {
-            for x in min_x..(max_x + 1) {
-                let v = *self.grid.get(&(x, y)).unwrap_or(&BLACK);
-
-                match v {
-                    WHITE => {
-                        print!("#");
-                    },
-                    BLACK => {
-                        print!(" ");
-                    },
-                    _ => unreachable!()
-                };
-            }
-            println!("");
+        (min_y..(max_y + 1))
+            .map(|y| (min_x..(max_x + 1)).map(|x| self.grid.get(&(x, y)).cloned()).collect())
+            .collect()
+    }
+
+    pub fn render(&self) -> String {
+        self.pixels()
+            .iter()
+            .map(|row| row.iter().map(|&white| if white { '#' } else { ' ' }).collect::<String>() + "\n")
+            .collect()
+    }
+
+    /// Read the registration identifier painted on the hull.
+    pub fn letters(&self) -> Result<String, OcrError> {
+        Grid::from_points(&self.grid).read()
+    }
+
+    pub fn print(&self) {
+        print!("{}", self.render());
+    }
+
+    /// The hull as a plain PBM image, white paint drawn in black.
+    pub fn to_pbm(&self) -> String {
+        let pixels = self.pixels();
+        let mut image = format!("P1\n{} {}\n", pixels.first().map(|r| r.len()).unwrap_or(0), pixels.len());
+
+        for row in pixels {
+            let line : Vec<&str> = row.iter().map(|&white| if white { "1" } else { "0" }).collect();
+            image.push_str(&line.join(" "));
+            image.push('\n');
+        }
+        image
+    }
+
+    /// The hull as a plain PGM image, with panels that were never painted
+    /// shown in grey.
+    pub fn to_pgm(&self) -> String {
+        let cells = self.cells();
+        let mut image = format!("P2\n{} {}\n2\n", cells.first().map(|r| r.len()).unwrap_or(0), cells.len());
+
+        for row in cells {
+            let line : Vec<&str> = row
+                .iter()
+                .map(|v| match v {
+                    Some(WHITE) => "2",
+                    Some(_)     => "0",
+                    None        => "1"
+                })
+                .collect();
+            image.push_str(&line.join(" "));
+            image.push('\n');
         }
+        image
     }
 
     pub fn paint(&mut self, pos: Point, color: isize) {
@@ -86,33 +127,30 @@ impl Robot {
                     self.pos.1 + self.facing.1);
     }
 
-    pub fn execute(&mut self, mut p: Program) -> Result<(), InvalidInstruction> {
-        Computer::run(&mut p, |event, _|{
-            match event {
-                IOEvent::Input => {
-                    let color = *self.grid.get(&self.pos).unwrap_or(&BLACK);
-                    Some(color)
-                }
-                IOEvent::Output(output) => {
-                    match self.state {
-                        RoboState::Paint => {
-                            self.paint(self.pos, output);
-                            self.state = RoboState::Turn;
-                        },
-                        RoboState::Turn => {
-                            self.turn(output);
-                            self.forward();
-                            self.state = RoboState::Paint;
-                        }
-                    };
-                    None
-                }
+    pub fn execute(&mut self, p: Program) -> Result<(), InvalidInstruction> {
+        let machine = Machine::spawn(p);
+
+        // report the current color, then read back a color to paint and a turn
+        loop {
+            let color = *self.grid.get(&self.pos).unwrap_or(&BLACK);
+            if machine.send(color).is_err() {
+                break;
             }
-        })
+
+            match (machine.recv(), machine.recv()) {
+                (Some(paint), Some(turn)) => {
+                    self.paint(self.pos, paint);
+                    self.turn(turn);
+                    self.forward();
+                },
+                _ => break
+            };
+        }
+
+        machine.join().map(|_| ())
     }
 }
 
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -134,7 +172,20 @@ mod test {
         r.paint((0, 0), WHITE);
         r.execute(p).expect("Failed to execute program");
 
-        r.print();
+        assert_eq!(r.letters(), Ok("ABEKZGFG".to_string()));
+        assert_eq!(r.render().lines().next(), Some("  ##  ###  #### #  # ####  ##  ####  ##    "));
+    }
+
+    #[test]
+    fn images() {
+        let mut r = Robot::new();
+        r.paint((0, 0), WHITE);
+        r.paint((1, 0), BLACK);
+        r.paint((1, 1), WHITE);
+
+        assert_eq!(r.render(), "# \n #\n");
+        assert_eq!(r.to_pbm(), "P1\n2 2\n1 0\n0 1\n");
+        assert_eq!(r.to_pgm(), "P2\n2 2\n2\n2 0\n1 2\n");
     }
 
 }
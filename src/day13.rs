@@ -1,8 +1,12 @@
 const INPUT : &'static str = include_str!("../inputs/day13.txt");
 
-use std::collections::HashMap;
-//use std::io;
-use crate::intcode::{Program, Computer, InvalidInstruction, IOEvent};
+use std::collections::{BTreeMap,HashMap,VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self,BufRead,BufReader,BufWriter,Read,Write};
+use std::path::Path;
+use std::process::Command;
+use crate::intcode::{Program, ProgramState, Snapshot, Computer, InvalidInstruction, IOEvent};
 
 const EMPTY : isize = 0;
 const WALL  : isize = 1;
@@ -10,138 +14,586 @@ const BLOCK : isize = 2;
 const PADDLE: isize = 3;
 const BALL  : isize = 4;
 
-type Point = (isize, isize);
+// How many save states `Arcade::solve` keeps, and how often it gives up.
+const MAX_SAVES   : usize = 64;
+const MAX_REWINDS : usize = 10_000;
 
-pub struct Arcade {
-    grid: HashMap<Point, isize>,
-    score: isize,
-}
+type Point = (isize, isize);
 
+#[derive(Clone)]
 pub enum LoadState {
     XPos,
     YPos,
     Type
 }
 
-impl Arcade {
-    pub fn draw(grid: &HashMap<Point, isize>) {
-        let min_x = grid.keys().min_by_key(|p| p.0).unwrap().0;
-        let max_x = grid.keys().max_by_key(|p| p.0).unwrap().0;
-        let min_y = grid.keys().min_by_key(|p| p.1).unwrap().1;
-        let max_y = grid.keys().max_by_key(|p| p.1).unwrap().1;
+/// Everything the arcade cabinet has drawn so far.
+#[derive(Clone)]
+pub struct Game {
+    grid:      HashMap<Point, isize>,
+    score:     isize,
+    ball:      Option<Point>,
+    last_ball: Option<Point>,
+    paddle:    Option<Point>,
+    /// The number of joystick inputs read so far.
+    frame:     usize,
+    state:     LoadState,
+    x:         isize,
+    y:         isize
+}
+
+impl Game {
+    pub fn new() -> Game {
+        Game {
+            grid:      HashMap::new(),
+            score:     0,
+            ball:      None,
+            last_ball: None,
+            paddle:    None,
+            frame:     0,
+            state:     LoadState::XPos,
+            x:         0,
+            y:         0
+        }
+    }
+
+    /// Handle one value output by the cabinet; every third completes a tile
+    /// or score update.
+    pub fn update(&mut self, v: isize) {
+        match self.state {
+            LoadState::XPos => {
+                self.x = v;
+                self.state = LoadState::YPos;
+            },
+            LoadState::YPos => {
+                self.y = v;
+                self.state = LoadState::Type;
+            },
+            LoadState::Type => {
+                let p = (self.x, self.y);
+                if p == (-1, 0) {
+                    self.score = v;
+                } else {
+                    self.grid.insert(p, v);
+
+                    if v == PADDLE {
+                        self.paddle = Some(p);
+                    } else if v == BALL {
+                        self.last_ball = self.ball;
+                        self.ball = Some(p);
+                    }
+                }
+                self.state = LoadState::XPos;
+            }
+        }
+    }
+
+    pub fn score(&self) -> isize {
+        self.score
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn ball(&self) -> Option<Point> {
+        self.ball
+    }
+
+    pub fn paddle(&self) -> Option<Point> {
+        self.paddle
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.grid.values().filter(|&&v| v == BLOCK).count()
+    }
+
+    /// The direction the ball moved in since it was last drawn.
+    pub fn velocity(&self) -> Option<Point> {
+        match (self.last_ball, self.ball) {
+            (Some(a), Some(b)) => Some((b.0 - a.0, b.1 - a.1)),
+            _ => None
+        }
+    }
+
+    /// The leftmost and rightmost columns the ball can occupy, if any walls
+    /// have been drawn.
+    fn bounds(&self) -> Option<(isize, isize)> {
+        let walls = self.grid.iter().filter(|(_, &v)| v == WALL).map(|(p, _)| p.0);
+        let min = walls.clone().min()?;
+        let max = walls.max()?;
+        Some((min + 1, max - 1))
+    }
+}
+
+impl Default for Game {
+    fn default() -> Game {
+        Game::new()
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if self.grid.is_empty() {
+            return writeln!(f, "Score: {}", self.score);
+        }
+
+        let min_x = self.grid.keys().min_by_key(|p| p.0).unwrap().0;
+        let max_x = self.grid.keys().max_by_key(|p| p.0).unwrap().0;
+        let min_y = self.grid.keys().min_by_key(|p| p.1).unwrap().1;
+        let max_y = self.grid.keys().max_by_key(|p| p.1).unwrap().1;
 
         for y in min_y..(max_y + 1) {
             for x in min_x..(max_x + 1) {
-                let v = *grid.get(&(x, y)).unwrap_or(&EMPTY);
-
-                match v {
-                    EMPTY  => print!(" "),
-                    WALL   => print!("#"),
-                    BLOCK  => print!("."),
-                    PADDLE => print!("-"),
-                    BALL   => print!("o"),
-                    _ => unreachable!()
+                let ch = match *self.grid.get(&(x, y)).unwrap_or(&EMPTY) {
+                    WALL   => '#',
+                    BLOCK  => '.',
+                    PADDLE => '-',
+                    BALL   => 'o',
+                    _      => ' '
                 };
+                write!(f, "{}", ch)?;
             }
-            println!("");
+            writeln!(f)?;
+        }
+        writeln!(f, "Score: {}", self.score)
+    }
+}
+
+/// Decides how to move the joystick: -1 for left, 0 for neutral, 1 for
+/// right. Returning `None` stops the game.
+pub trait JoystickStrategy {
+    fn joystick(&mut self, game: &Game) -> Option<isize>;
+}
+
+/// Keep the paddle under the ball.
+pub struct Tracking;
+
+impl JoystickStrategy for Tracking {
+    fn joystick(&mut self, game: &Game) -> Option<isize> {
+        match (game.ball(), game.paddle()) {
+            (Some(ball), Some(paddle)) => Some((ball.0 - paddle.0).signum()),
+            _ => Some(0)
+        }
+    }
+}
+
+/// Move the paddle to where the ball will come down, assuming it only
+/// bounces off the side walls on the way.
+pub struct Predictive;
+
+impl Predictive {
+    pub fn landing(game: &Game) -> Option<isize> {
+        let ball = game.ball()?;
+        let paddle = game.paddle()?;
+        let (dx, dy) = game.velocity()?;
+        if dy <= 0 {
+            return None;
+        }
+
+        let (min, max) = game.bounds()?;
+        let width = max - min;
+        if width <= 0 {
+            return Some(min);
+        }
+
+        // unfold the bounces into a straight line, then fold it back
+        let x = ball.0 - min + dx * (paddle.1 - 1 - ball.1);
+        let x = x.rem_euclid(2 * width);
+        Some(min + if x > width { 2 * width - x } else { x })
+    }
+}
+
+impl JoystickStrategy for Predictive {
+    fn joystick(&mut self, game: &Game) -> Option<isize> {
+        let target = Predictive::landing(game).or(game.ball().map(|b| b.0));
+        match (target, game.paddle()) {
+            (Some(x), Some(paddle)) => Some((x - paddle.0).signum()),
+            _ => Some(0)
+        }
+    }
+}
+
+/// Replay a fixed sequence of inputs, stopping when it runs out.
+pub struct Recorded {
+    inputs: Vec<isize>,
+    next:   usize
+}
+
+impl Recorded {
+    pub fn new(inputs: Vec<isize>) -> Recorded {
+        Recorded { inputs, next: 0 }
+    }
+}
+
+impl JoystickStrategy for Recorded {
+    fn joystick(&mut self, _game: &Game) -> Option<isize> {
+        let input = self.inputs.get(self.next).cloned();
+        self.next += 1;
+        input
+    }
+}
+
+/// Save a session's joystick inputs, one per line, for use with `Recorded`.
+pub fn save_recording<P: AsRef<Path>>(inputs: &[isize], path: P) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+
+    writeln!(w, "# joystick")?;
+    for input in inputs {
+        writeln!(w, "{}", input)?;
+    }
+    w.flush()
+}
+
+pub fn load_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<isize>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| line.as_ref().map(|l| !l.trim().is_empty() && !l.starts_with('#')).unwrap_or(true))
+        .map(|line|{
+            let line = line?;
+            line.trim()
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid input: {}", line)))
+        })
+        .collect()
+}
+
+// The joystick position for a keypress: a/h/left arrow and d/l/right arrow
+// move, q quits and anything else leaves the joystick neutral.
+fn key_input(key: &[u8]) -> Option<isize> {
+    match key {
+        b"a" | b"h" | b"\x1b[D" => Some(-1),
+        b"d" | b"l" | b"\x1b[C" => Some(1),
+        b"q"                    => None,
+        _                       => Some(0)
+    }
+}
+
+/// Play from the terminal, one keypress per frame. The board is redrawn in
+/// place, and the terminal is put back to normal when this is dropped.
+pub struct Interactive {
+    tty: File
+}
+
+impl Interactive {
+    pub fn new() -> io::Result<Interactive> {
+        let tty = File::open("/dev/tty")?;
+        // read keys as they are pressed, without echoing them
+        let status = Command::new("stty").args(["-icanon", "-echo", "min", "1"]).stdin(tty.try_clone()?).status()?;
+        if !status.success() {
+            return Err(io::Error::other("stty failed"));
+        }
+
+        // clear the screen and hide the cursor
+        print!("\x1b[2J\x1b[?25l");
+        Ok(Interactive { tty })
+    }
+}
+
+impl Drop for Interactive {
+    fn drop(&mut self) {
+        print!("\x1b[?25h");
+        io::stdout().flush().ok();
+        if let Ok(tty) = self.tty.try_clone() {
+            Command::new("stty").args(["icanon", "echo"]).stdin(tty).status().ok();
         }
-        println!("");
+    }
+}
+
+impl JoystickStrategy for Interactive {
+    fn joystick(&mut self, game: &Game) -> Option<isize> {
+        print!("\x1b[H{}\x1b[K\n[a] left  [s] stay  [d] right  [q] quit  frame {}\x1b[K\n", game, game.frame());
+        io::stdout().flush().ok()?;
+
+        // an arrow key arrives as a single three byte read
+        let mut key = [0; 3];
+        let n = self.tty.read(&mut key).ok()?;
+        if n == 0 {
+            return None;
+        }
+        key_input(&key[..n])
+    }
+}
+
+/// Runs the arcade program, feeding it input from a `JoystickStrategy`.
+pub struct Arcade {
+    program: Program,
+    game:    Game,
+    inputs:  Vec<isize>,
+    display: bool
+}
+
+impl Arcade {
+    pub fn new(program: Program) -> Arcade {
+        Arcade { program, game: Game::new(), inputs: vec![], display: false }
+    }
+
+    /// Set the game to free play instead of just drawing the screen.
+    pub fn insert_quarters(mut self) -> Arcade {
+        self.program.set_mem(0, 2);
+        self
     }
 
-    pub fn initialize(p: &mut Program) -> Result<Arcade, InvalidInstruction> {
-        let mut grid = HashMap::new();
-        let mut state = LoadState::XPos;
-        let mut x = 0;
-        let mut y = 0;
-        let mut t = 0;
-        let mut score = 0;
-        let mut ball = (0, 0);
-        let mut paddle = (0, 0);
+    /// Print the screen each time the joystick is read.
+    pub fn display(mut self, display: bool) -> Arcade {
+        self.display = display;
+        self
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
 
-        Computer::run( p, |event, _| {
+    /// Every joystick input given so far.
+    pub fn inputs(&self) -> &[isize] {
+        &self.inputs
+    }
+
+    /// Give the joystick `input`, if any, and run until the program asks
+    /// for the next one. Returns false once the program has halted.
+    pub fn advance(&mut self, input: Option<isize>) -> Result<bool, InvalidInstruction> {
+        let game = &mut self.game;
+        let inputs = &mut self.inputs;
+        let mut input = input;
+
+        Computer::run(&mut self.program, |event, computer| {
             match event {
                 IOEvent::Input => {
-                    //  let mut s = String::new();
-                    // std::io::stdin().read_line(&mut s).unwrap();
-                    // match s.trim() {
-                    //     "l" => Some(-1),
-                    //     "r" => Some(1),
-                    //     _   => Some(0)
-                    // }
-                    if ball.0 < paddle.0 {
-                        Some(-1)
-                    } else if ball.0 > paddle.0 {
-                        Some(1)
-                    } else {
-                        Some(0)
-                    }
+                    let value = input.take();
+                    match value {
+                        Some(v) => {
+                            game.frame += 1;
+                            inputs.push(v);
+                        },
+                        None => computer.abort()
+                    };
+                    value
                 },
                 IOEvent::Output(v) => {
-                    match state {
-                        LoadState::XPos => {
-                            x = v;
-                            state = LoadState::YPos;
-                        },
-                        LoadState::YPos => {
-                            y = v;
-                            state = LoadState::Type;
-                        },
-                        LoadState::Type => {
-                            t = v;
-                            if x == -1 && y == 0 {
-                                score = t;
-                            } else {
-                                grid.insert((x, y), t);
-
-                                if t == PADDLE {
-                                    paddle = (x, y);
-                                } else if t == BALL {
-                                    ball = (x, y);
-                                }
-                            }
-                            state = LoadState::XPos;
-                            Arcade::draw(&grid);
-                        }
-
-                    }
+                    game.update(v);
                     None
                 }
             }
-
         })?;
 
-        Ok(Arcade{
-            grid: grid,
-            score: score
-        })
+        Ok(self.program.state() != ProgramState::Halted)
+    }
+
+    /// Play until the program halts or the strategy gives up.
+    pub fn play<S: JoystickStrategy>(&mut self, strategy: &mut S) -> Result<&Game, InvalidInstruction> {
+        let mut input = None;
+
+        while self.advance(input)? {
+            if self.display {
+                println!("{}", self.game);
+            }
+
+            input = strategy.joystick(&self.game);
+            if input.is_none() {
+                break;
+            }
+        }
+
+        Ok(&self.game)
+    }
+
+    pub fn save(&self) -> SaveState {
+        SaveState {
+            snapshot: self.program.snapshot(),
+            game:     self.game.clone(),
+            inputs:   self.inputs.len()
+        }
+    }
+
+    pub fn load(&mut self, save: &SaveState) {
+        self.program = Program::restore(&save.snapshot);
+        self.game = save.game.clone();
+        self.inputs.truncate(save.inputs);
+    }
+
+    /// Play by trial and error instead of following the ball. Whenever the
+    /// ball is missed, the frame and column where it came down are noted and
+    /// the game is rewound at least `rewind` frames, after which the paddle
+    /// heads for the next noted landing spot in time. Saves are taken every
+    /// `interval` frames.
+    ///
+    /// Returns the finished game and the number of rewinds it took.
+    pub fn solve(&mut self, interval: usize, rewind: usize) -> Result<(&Game, usize), InvalidInstruction> {
+        let mut saves : VecDeque<SaveState> = VecDeque::new();
+        let mut landings : BTreeMap<usize, isize> = BTreeMap::new();
+        let mut landing = None;
+        let mut depth = rewind;
+        let mut rewinds = 0;
+
+        if !self.advance(None)? {
+            return Ok((&self.game, rewinds));
+        }
+
+        loop {
+            let frame = self.game.frame;
+            if frame.is_multiple_of(interval) && saves.back().map(|s| s.game.frame != frame).unwrap_or(true) {
+                saves.push_back(self.save());
+                if saves.len() > MAX_SAVES {
+                    saves.pop_front();
+                }
+            }
+
+            let input = match (landings.range(frame..).next(), self.game.paddle) {
+                (Some((_, &x)), Some(paddle)) => (x - paddle.0).signum(),
+                _ => 0
+            };
+            let running = self.advance(Some(input))?;
+
+            let (ball, paddle) = match (self.game.ball, self.game.paddle) {
+                (Some(ball), Some(paddle)) => (ball, paddle),
+                _ if running => continue,
+                _ => break
+            };
+            if ball.1 == paddle.1 - 1 {
+                landing = Some((self.game.frame, ball.0));
+            }
+
+            let missed = ball.1 >= paddle.1 || (!running && self.game.blocks() > 0);
+            if !missed {
+                if running {
+                    continue;
+                }
+                break;
+            }
+            let (at, x) = match landing {
+                Some(landing) if rewinds < MAX_REWINDS => landing,
+                _ => break
+            };
+
+            // missing a spot we were already heading for means the paddle
+            // needs longer to get there
+            depth = if landings.insert(at, x) == Some(x) { depth * 2 } else { rewind };
+            rewinds += 1;
+
+            let to = self.game.frame.saturating_sub(depth);
+            while saves.len() > 1 && saves.back().map(|s| s.game.frame > to).unwrap_or(false) {
+                saves.pop_back();
+            }
+            match saves.back() {
+                Some(save) => self.load(save),
+                None       => break
+            };
+        }
+
+        Ok((&self.game, rewinds))
     }
 }
 
+/// A point in a game that `Arcade::load` can go back to.
+#[derive(Clone)]
+pub struct SaveState {
+    snapshot: Snapshot,
+    game:     Game,
+    inputs:   usize
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn arcade() -> Arcade {
+        Arcade::new(Program::parse(INPUT).expect("Failed to load input"))
+    }
+
     #[test]
     fn p1_solution() {
-        let mut p = Program::parse(INPUT).expect("Failed to load input");
+        let mut a = arcade();
+        let game = a.play(&mut Tracking).unwrap();
 
-        let a = Arcade::initialize(&mut p).unwrap();
-        let c = a.grid.values()
-            .filter(|&&v| v == BLOCK)
-            .count();
-
-        assert_eq!(c, 255);
+        assert_eq!(game.blocks(), 255);
     }
 
     #[test]
     fn p2_solution() {
-        let mut p = Program::parse(INPUT).expect("Failed to load input");
-        p.set_mem(0, 2);
+        let mut a = arcade().insert_quarters();
+        let game = a.play(&mut Tracking).unwrap();
 
-        let a = Arcade::initialize(&mut p).unwrap();
+        assert_eq!(game.blocks(), 0);
+        assert_eq!(game.score(), 12338);
+    }
 
-        assert_eq!(a.score, 12338);
+    #[test]
+    fn predictive_and_recorded() {
+        let mut a = arcade().insert_quarters();
+        assert_eq!(a.play(&mut Predictive).unwrap().score(), 12338);
 
+        let mut replay = arcade().insert_quarters();
+        let game = replay.play(&mut Recorded::new(a.inputs().to_vec())).unwrap();
+        assert_eq!(game.score(), 12338);
+        assert_eq!(game.frame(), a.game().frame());
     }
 
+    #[test]
+    fn landing_without_walls() {
+        let mut game = Game::new();
+        for &v in &[5, 1, BALL, 5, 2, BALL, 5, 9, PADDLE] {
+            game.update(v);
+        }
+        assert_eq!(game.velocity(), Some((0, 1)));
+        assert_eq!(game.bounds(), None);
+        assert_eq!(Predictive::landing(&game), None);
+
+        for &v in &[0, 0, WALL, 10, 0, WALL] {
+            game.update(v);
+        }
+        assert_eq!(Predictive::landing(&game), Some(5));
+    }
+
+    #[test]
+    fn p2_rewind() {
+        let mut a = arcade().insert_quarters();
+        let (game, rewinds) = a.solve(5, 10).unwrap();
+
+        assert_eq!(game.blocks(), 0);
+        assert_eq!(game.score(), 12338);
+        assert!(rewinds > 0);
+
+        // the inputs that survived rewinding win the game on their own
+        let inputs = a.inputs().to_vec();
+        let mut replay = arcade().insert_quarters();
+        assert_eq!(replay.play(&mut Recorded::new(inputs)).unwrap().score(), 12338);
+    }
+
+    #[test]
+    fn recording_round_trip() {
+        let mut a = arcade().insert_quarters();
+        let game = a.play(&mut Recorded::new(vec![0, 1, 1, -1])).unwrap();
+        assert_eq!(game.frame(), 4);
+
+        let path = std::env::temp_dir().join(format!("aoc-day13-recording_round_trip-{}.txt", std::process::id()));
+        save_recording(a.inputs(), &path).expect("Failed to save recording");
+        let inputs = load_recording(&path).expect("Failed to load recording");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(inputs, vec![0, 1, 1, -1]);
+
+        assert_eq!(key_input(b"\x1b[D"), Some(-1));
+        assert_eq!(key_input(b"d"), Some(1));
+        assert_eq!(key_input(b" "), Some(0));
+        assert_eq!(key_input(b"q"), None);
+    }
+
+    // Play the game yourself, then replay the saved session headless:
+    // cargo test play_interactive -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn play_interactive() {
+        let path = std::env::temp_dir().join("aoc-day13-session.txt");
+        let mut a = arcade().insert_quarters();
+        {
+            let mut terminal = Interactive::new().expect("Unable to set up the terminal");
+            a.play(&mut terminal).unwrap();
+        }
+        println!("{}", a.game());
+        save_recording(a.inputs(), &path).expect("Failed to save recording");
+        println!("{} inputs saved to {}", a.inputs().len(), path.display());
+
+        let inputs = load_recording(&path).expect("Failed to load recording");
+        let mut replay = arcade().insert_quarters();
+        assert_eq!(replay.play(&mut Recorded::new(inputs)).unwrap().score(), a.game().score());
+    }
 }
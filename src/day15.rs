@@ -2,8 +2,7 @@ const INPUT : &'static str = include_str!("../inputs/day15.txt");
 
 
 use crate::intcode::{Program,Computer,InvalidInstruction,IOEvent};
-use std::collections::{HashMap,HashSet};
-use rand::prelude::*;
+use std::collections::{HashMap,VecDeque};
 
 type Point = (isize, isize);
 
@@ -49,94 +48,116 @@ impl Area {
         println!("");
     }
 
-    pub fn find_oxygen(&mut self, mut p: &mut Program) -> Result<Point,InvalidInstruction> {
-        let mut next = (0, 0);
-        let mut rng = rand::thread_rng();
-        Computer::run(&mut p, |event, computer| {
+    fn neighbors(p: Point) -> Vec<(Point, isize)> {
+        vec![
+            ((p.0, p.1 - 1), 1),
+            ((p.0, p.1 + 1), 2),
+            ((p.0 - 1, p.1), 3),
+            ((p.0 + 1, p.1), 4)
+        ]
+    }
+
+    // The command that undoes moving in direction `dir`.
+    fn reverse(dir: isize) -> isize {
+        match dir {
+            1 => 2,
+            2 => 1,
+            3 => 4,
+            4 => 3,
+            _ => unreachable!()
+        }
+    }
+
+    /// Map the whole area with a depth first search, walking back the way the
+    /// droid came whenever it runs out of unknown cells to try. Returns the
+    /// location of the oxygen system, if there is one.
+    pub fn explore(&mut self, p: &mut Program) -> Result<Option<Point>,InvalidInstruction> {
+        // directions taken to reach the droid's position from the start
+        let mut path : Vec<isize> = vec![];
+        let mut next = (self.droid, 0, false);
+        let mut oxygen = None;
+
+        Computer::run(p, |event, computer| {
             match event {
                 IOEvent::Output(rc) => {
+                    let (point, dir, backtracking) = next;
                     match rc {
                         0 => {
-                            self.map.insert(next, WALL);
-                            next = self.droid;
-                        }
-                        1 => {
-                            self.map.insert(next, OPEN);
-                            self.droid = next;
+                            self.map.insert(point, WALL);
+                            return None;
                         },
+                        1 => { self.map.insert(point, OPEN); },
                         2 => {
-                            self.map.insert(next, OXYGEN);
-                            self.droid = next;
-                            computer.abort();
-                            println!("found oxygen at {:?}", next);
-                            self.print();
+                            self.map.insert(point, OXYGEN);
+                            oxygen = Some(point);
                         },
                         _ => unreachable!()
                     };
 
+                    self.droid = point;
+                    if !backtracking {
+                        path.push(dir);
+                    }
                     None
                 },
                 IOEvent::Input => {
-                    let around = vec![
-                        ((self.droid.0, self.droid.1 - 1), 1),
-                        ((self.droid.0, self.droid.1 + 1), 2),
-                        ((self.droid.0 - 1, self.droid.1), 3),
-                        ((self.droid.0 + 1, self.droid.1), 4)
-                    ];
-
-                    let mut possible = around
-                        .iter()
-                        .filter(|(p, _)| self.map.get(&p).unwrap_or(&UNKNOWN) == &UNKNOWN)
-                        .collect::<Vec<&(Point, isize)>>();
-
-                    if possible.is_empty() {
-                        possible = around
-                            .iter()
-                            .filter(|(p, _)| self.map.get(&p).unwrap_or(&UNKNOWN) == &OPEN)
-                            .collect::<Vec<&(Point, isize)>>();
+                    let unknown = Area::neighbors(self.droid)
+                        .into_iter()
+                        .find(|(p, _)| !self.map.contains_key(p));
+
+                    if let Some((point, dir)) = unknown {
+                        next = (point, dir, false);
+                        return Some(dir);
                     }
 
-                    let (n, instr) = possible[rng.next_u32() as usize % possible.len()];
-                    next = *n;
-                    Some(*instr)
+                    match path.pop() {
+                        Some(dir) => {
+                            let dir = Area::reverse(dir);
+                            let point = Area::neighbors(self.droid)[dir as usize - 1].0;
+                            next = (point, dir, true);
+                            Some(dir)
+                        },
+                        None => {
+                            // back at the start with nothing left to visit
+                            computer.abort();
+                            None
+                        }
+                    }
                 }
             }
         })?;
 
-        Ok(self.droid)
+        Ok(oxygen)
     }
 
-    pub fn min_distance(&self, from: Point, to: Point, mut used: &mut HashSet<Point>) -> Option<usize> {
-        if from == to {
-            println!("found dest at {:?}", from);
-            return Some(0);
+    /// The number of steps from `from` to every reachable open cell.
+    pub fn distances(&self, from: Point) -> HashMap<Point, usize> {
+        let mut dist = HashMap::new();
+        let mut queue = VecDeque::new();
+        dist.insert(from, 0);
+        queue.push_back(from);
+
+        while let Some(p) = queue.pop_front() {
+            let d = dist[&p];
+            for (n, _) in Area::neighbors(p) {
+                let open = matches!(self.map.get(&n), Some(&OPEN) | Some(&OXYGEN));
+                if open && !dist.contains_key(&n) {
+                    dist.insert(n, d + 1);
+                    queue.push_back(n);
+                }
+            }
         }
 
-        let neighbors = vec![(0, -1), (0, 1), (-1, 0), (1, 0)];
-        let neighbors = neighbors
-            .iter()
-            .filter_map(|&(px, py)|{
-                let point = (from.0 + px, from.1 + py);
-                let value = self.map.get(&point).unwrap_or(&UNKNOWN);
-                let ok = (value == &OPEN || value == &OXYGEN) && used.insert(point);
-                if ok {
-                    Some(point)
-                } else {
-                    None
-                }
-            })
-            .inspect(|p| println!("exploring {:?}", p))
-            .collect::<Vec<Point>>();
-
-        neighbors
-            .iter()
-            .filter_map(|&point|{
-                match self.min_distance(point, to, &mut used) {
-                    Some(v) => Some(v + 1),
-                    None => None
-                }
-            })
-            .min()
+        dist
+    }
+
+    pub fn min_distance(&self, from: Point, to: Point) -> Option<usize> {
+        self.distances(from).get(&to).cloned()
+    }
+
+    /// Minutes for oxygen to spread from `from` to the whole area.
+    pub fn fill_time(&self, from: Point) -> usize {
+        self.distances(from).values().cloned().max().unwrap_or(0)
     }
 }
 
@@ -145,15 +166,28 @@ impl Area {
 mod test {
     use super::*;
 
-    #[test]
-    fn p1_solution() {
+    fn explored() -> (Area, Point) {
         let mut p = Program::parse(INPUT).expect("Failed to parse program");
         let mut a = Area::new();
 
-        let oxygen = a.find_oxygen(&mut p).unwrap();
+        let oxygen = a.explore(&mut p).unwrap().expect("No oxygen system found");
+        (a, oxygen)
+    }
+
+    #[test]
+    fn p1_solution() {
+        let (a, oxygen) = explored();
+
+        // every open cell has had all of its neighbors mapped
+        assert!(a.distances((0, 0)).keys().all(|&p| Area::neighbors(p).iter().all(|(n, _)| a.map.contains_key(n))));
+        assert_eq!(a.droid, (0, 0));
+        assert_eq!(a.min_distance((0, 0), oxygen), Some(244));
+    }
 
-        let distance = a.min_distance((0, 0), oxygen, &mut HashSet::new());
+    #[test]
+    fn p2_solution() {
+        let (a, oxygen) = explored();
 
-        assert_eq!(distance, Some(244));
+        assert_eq!(a.fill_time(oxygen), 278);
     }
 }
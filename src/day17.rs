@@ -1,67 +1,332 @@
 const INPUT : &'static str = include_str!("../inputs/day17.txt");
 
-use std::collections::HashMap;
-use crate::intcode::{Computer,Program,InvalidInstruction,IOEvent};
+use std::collections::HashSet;
+use std::fmt;
+use crate::intcode::{Program,InvalidInstruction,run_ascii};
 
+// Longest routine the robot's memory can hold, not counting the newline.
+const MAX_ROUTINE : usize = 20;
 
-type Point = (usize, usize);
+type Point = (isize, isize);
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Facing {
+    Up,
+    Right,
+    Down,
+    Left
+}
+
+impl Facing {
+    fn from_char(ch: char) -> Option<Facing> {
+        match ch {
+            '^' => Some(Facing::Up),
+            '>' => Some(Facing::Right),
+            'v' => Some(Facing::Down),
+            '<' => Some(Facing::Left),
+            _   => None
+        }
+    }
+
+    fn delta(&self) -> Point {
+        match self {
+            Facing::Up    => (0, -1),
+            Facing::Right => (1, 0),
+            Facing::Down  => (0, 1),
+            Facing::Left  => (-1, 0)
+        }
+    }
+
+    fn left(&self) -> Facing {
+        match self {
+            Facing::Up    => Facing::Left,
+            Facing::Right => Facing::Up,
+            Facing::Down  => Facing::Right,
+            Facing::Left  => Facing::Down
+        }
+    }
+
+    fn right(&self) -> Facing {
+        self.left().left().left()
+    }
+}
+
+/// A turn followed by a number of steps forward, or steps forward without
+/// turning.
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Move {
+    Left(usize),
+    Right(usize),
+    Forward(usize)
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Move::Left(n)    => write!(f, "L,{}", n),
+            Move::Right(n)   => write!(f, "R,{}", n),
+            Move::Forward(n) => write!(f, "{}", n)
+        }
+    }
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn offset(p: Point, d: Point) -> Point {
+    (p.0 + d.0, p.1 + d.1)
+}
+
+/// The scaffold as seen by the camera, and the robot on it.
+struct Scaffold {
+    cells:  HashSet<Point>,
+    robot:  Point,
+    facing: Facing
+}
+
+impl Scaffold {
+    fn parse(text: &str) -> Option<Scaffold> {
+        let mut cells = HashSet::new();
+        let mut robot = None;
+
+        for (y, line) in text.lines().enumerate() {
+            for (x, ch) in line.chars().enumerate() {
+                let p = (x as isize, y as isize);
+                if ch == '#' {
+                    cells.insert(p);
+                } else if let Some(facing) = Facing::from_char(ch) {
+                    cells.insert(p);
+                    robot = Some((p, facing));
+                }
+            }
+        }
+
+        robot.map(|(robot, facing)| Scaffold { cells, robot, facing })
+    }
+
+    fn intersections(&self) -> Vec<Point> {
+        let neighbors = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+        self.cells
+            .iter()
+            .filter(|&&p| neighbors.iter().all(|&d| self.cells.contains(&offset(p, d))))
+            .cloned()
+            .collect()
+    }
+
+    fn alignment(&self) -> isize {
+        self.intersections().iter().map(|(x, y)| x * y).sum()
+    }
+
+    /// Follow the scaffold from the robot to its far end, going straight over
+    /// intersections and turning only at corners.
+    fn path(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        let mut pos = self.robot;
+        let mut facing = self.facing;
+
+        // the robot may already be facing along the scaffold, or away from
+        // it, in which case it turns once on the spot and again below
+        let steps = self.walk(&mut pos, facing);
+        if steps > 0 {
+            moves.push(Move::Forward(steps));
+        } else if self.cells.contains(&offset(pos, facing.left().left().delta()))
+               && !self.cells.contains(&offset(pos, facing.left().delta()))
+               && !self.cells.contains(&offset(pos, facing.right().delta())) {
+            moves.push(Move::Left(0));
+            facing = facing.left();
+        }
+
+        loop {
+            let left = if self.cells.contains(&offset(pos, facing.left().delta())) {
+                true
+            } else if self.cells.contains(&offset(pos, facing.right().delta())) {
+                false
+            } else {
+                break;
+            };
+
+            facing = if left { facing.left() } else { facing.right() };
+            let steps = self.walk(&mut pos, facing);
+            moves.push(if left { Move::Left(steps) } else { Move::Right(steps) });
+        }
+
+        moves
+    }
+
+    // Move forward until the scaffold ends, returning the number of steps.
+    fn walk(&self, pos: &mut Point, facing: Facing) -> usize {
+        let mut steps = 0;
+        while self.cells.contains(&offset(*pos, facing.delta())) {
+            *pos = offset(*pos, facing.delta());
+            steps += 1;
+        }
+        steps
+    }
+}
+
+/// A path split into a main routine calling up to three movement functions.
+#[derive(Debug,Clone,PartialEq)]
+struct Routines {
+    main:      String,
+    functions: Vec<String>
+}
+
+impl Routines {
+    fn input(&self, video: bool) -> String {
+        let mut lines = vec![self.main.clone()];
+        lines.extend(self.functions.iter().cloned());
+        // the robot always asks for three functions, even if some are never called
+        while lines.len() < 4 {
+            lines.push("L,0".to_string());
+        }
+        lines.push(if video { "y" } else { "n" }.to_string());
+        lines.join("\n")
+    }
+}
+
+// Depth first search for a split of `path`, reusing `functions` where they
+// match and otherwise trying each prefix that fits as a new function.
+fn compress_from<'a>(path: &'a [Move], functions: &mut Vec<&'a [Move]>, main: &mut Vec<usize>) -> bool {
+    if path.is_empty() {
+        return true;
+    }
+    // calls are single letters separated by commas
+    if (main.len() + 1) * 2 - 1 > MAX_ROUTINE {
+        return false;
+    }
+
+    for f in 0..functions.len() {
+        let function = functions[f];
+        if path.starts_with(function) {
+            main.push(f);
+            if compress_from(&path[function.len()..], functions, main) {
+                return true;
+            }
+            main.pop();
+        }
+    }
+
+    if functions.len() < 3 {
+        for len in 1..=path.len() {
+            if join(&path[..len]).len() > MAX_ROUTINE {
+                break;
+            }
+
+            functions.push(&path[..len]);
+            main.push(functions.len() - 1);
+            if compress_from(&path[len..], functions, main) {
+                return true;
+            }
+            main.pop();
+            functions.pop();
+        }
+    }
+
+    false
+}
+
+fn compress(path: &[Move]) -> Option<Routines> {
+    let mut functions = vec![];
+    let mut main = vec![];
+
+    if path.is_empty() || !compress_from(path, &mut functions, &mut main) {
+        return None;
+    }
+
+    let names : Vec<char> = main.iter().map(|&f| (b'A' + f as u8) as char).collect();
+    Some(Routines {
+        main:      join(&names),
+        functions: functions.iter().map(|f| join(f)).collect()
+    })
+}
+
+/// Wake the robot up and have it walk the whole scaffold, returning the
+/// amount of dust it reports collecting.
+fn collect_dust(program: &Program, routines: &Routines) -> Result<Option<isize>, InvalidInstruction> {
+    let mut p = program.clone();
+    p.set_mem(0, 2);
+
+    let output = run_ascii(&mut p, &routines.input(false))?;
+    Ok(output.values.last().cloned())
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn camera() -> Scaffold {
+        let mut p = Program::parse(INPUT).expect("Unable to load program");
+        let output = run_ascii(&mut p, "").expect("Error running program");
+
+        Scaffold::parse(&output.text).expect("No robot on camera")
+    }
+
     #[test]
     fn p1_solution() {
-        let mut p = Program::parse(INPUT).expect("Unable to load program");
+        assert_eq!(camera().alignment(), 6520);
+    }
 
-        let output = p.execute(&vec![]).expect("Error running program");
-
-        let mut grid = HashMap::new();
-
-        let mut x = 0;
-        let mut y = 0;
-
-        for value in &output {
-            let p = (x, y);
-            let ch = *value as u8 as char;
-            print!("{}", ch);
-            match ch {
-                '\n' => {
-                    x = 0;
-                    y += 1;
-                },
-                _ => {
-                    grid.insert(p, ch);
-                    x += 1;
-                }
+    #[test]
+    fn p2_example() {
+        let text = concat!(
+            "#######...#####\n",
+            "#.....#...#...#\n",
+            "#.....#...#...#\n",
+            "......#...#...#\n",
+            "......#...###.#\n",
+            "......#.....#.#\n",
+            "^########...#.#\n",
+            "......#.#...#.#\n",
+            "......#########\n",
+            "........#...#..\n",
+            "....#########..\n",
+            "....#...#......\n",
+            "....#...#......\n",
+            "....#...#......\n",
+            "....#####......\n"
+        );
+        let path = Scaffold::parse(text).unwrap().path();
+        assert_eq!(join(&path), "R,8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2");
 
-            }
-        }
+        let routines = compress(&path).expect("No compression found");
+        assert!(routines.main.len() <= MAX_ROUTINE);
+        assert!(routines.functions.iter().all(|f| f.len() <= MAX_ROUTINE));
 
-        let neighbors = vec![
-            (0, 1),
-            (1, 0),
-            (0, -1),
-            (-1, 0)
-        ];
+        let expanded : Vec<String> = routines.main
+            .split(',')
+            .map(|name| routines.functions[(name.as_bytes()[0] - b'A') as usize].clone())
+            .collect();
+        assert_eq!(expanded.join(","), join(&path));
+    }
 
-        let v = grid
-            .iter()
-            .filter(|&((px, py), &ch)|{
-                ch == '#' &&
-                    neighbors
-                    .iter()
-                    .all(|(dx, dy)|{
-                        grid
-                            .get(&(px + dx, py + dy))
-                            .unwrap_or(&' ') == &'#'
-                    })
-
-            })
-            .map(|((px, py), _)| px * py)
-            .sum::<i32>();
-
-
-        assert_eq!(v, 6520);
+    #[test]
+    fn path_initial_facing() {
+        let text = concat!(
+            "..#####\n",
+            "..#...#\n",
+            "..^...#\n",
+            "......#\n",
+            "....###\n"
+        );
+        let path = Scaffold::parse(text).unwrap().path();
+        assert_eq!(path, vec![Move::Forward(2), Move::Right(4), Move::Right(4), Move::Right(2)]);
+        assert_eq!(join(&path), "2,R,4,R,4,R,2");
+
+        // facing away from the scaffold
+        let path = Scaffold::parse("^\n#\n#\n").unwrap().path();
+        assert_eq!(join(&path), "L,0,L,2");
+
+        assert_eq!(Scaffold::parse("^\n").unwrap().path(), vec![]);
+        assert_eq!(compress(&[]), None);
+    }
+
+    #[test]
+    fn p2_solution() {
+        let p = Program::parse(INPUT).expect("Unable to load program");
+        let routines = compress(&camera().path()).expect("No compression found");
+
+        assert_eq!(collect_dust(&p, &routines), Ok(Some(1071369)));
     }
 }
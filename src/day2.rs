@@ -1,90 +1,108 @@
+use crate::intcode::{Program,InvalidInstruction,Limits,LimitReached,RunResult,Poly,analyze,search};
+use crate::intcode::SEARCH_STEP_LIMIT;
+
 use std::num::ParseIntError;
 
 const INPUT : &'static str  = include_str!("../inputs/day2.txt");
 
+#[derive(Debug,Clone,PartialEq)]
+enum EvalError {
+    Invalid(InvalidInstruction),
+    /// The program didn't halt within `SEARCH_STEP_LIMIT` steps.
+    Limited(LimitReached)
+}
+
+impl From<InvalidInstruction> for EvalError {
+    fn from(e: InvalidInstruction) -> EvalError {
+        EvalError::Invalid(e)
+    }
+}
+
 #[derive(Debug,PartialEq,Clone)]
 struct IntcodeComputer {
-    pub ops: Vec<usize>
+    program: Program
 }
 
 impl IntcodeComputer {
-    /// Create a new computer from a list of instructions.
-    pub fn new(ops: Vec<usize>) -> IntcodeComputer {
-        IntcodeComputer { ops: ops }
+    /// Create a new computer from a program.
+    pub fn new(program: Program) -> IntcodeComputer {
+        IntcodeComputer { program }
     }
 
     /// Create a new computer, if possible, from an input string of comma delimited
     /// integers.
     pub fn parse(line: &str) -> Result<IntcodeComputer, ParseIntError> {
-        let ops = line
-            .trim()
-            .split(",")
-            .map(|part| part.trim().parse::<usize>())
-            .collect::<Result<Vec<usize>, ParseIntError>>()?;
-
-        Ok(IntcodeComputer::new(ops))
+        Ok(IntcodeComputer::new(Program::parse(line)?))
     }
 
-    pub fn noun(&mut self, noun: usize) -> &mut IntcodeComputer {
-        self.ops[1] = noun;
-        self
+    /// The current contents of memory.
+    pub fn ops(&self) -> Vec<isize> {
+        self.program.mem_range(0, self.program.size())
     }
 
-    pub fn verb(&mut self, verb: usize) -> &mut IntcodeComputer {
-        self.ops[2] = verb;
+    pub fn noun(&mut self, noun: isize) -> &mut IntcodeComputer {
+        self.program.set_mem(1, noun);
         self
     }
 
-    pub fn output(&self) -> usize {
-        self.ops[0]
+    pub fn verb(&mut self, verb: isize) -> &mut IntcodeComputer {
+        self.program.set_mem(2, verb);
+        self
     }
 
-    /// Evaluate the instructions contained in the computer.
-    pub fn eval(&mut self) -> &IntcodeComputer {
-        let range = (0..self.ops.len()).step_by(4);
-
-        for i in range {
-            match self.ops[i] {
-                1 => self.eval_instruction(i, |a, b| a + b),
-                2 => self.eval_instruction(i, |a, b| a * b),
-                _ => break
-            }
-        };
-        self
+    pub fn output(&self) -> isize {
+        self.program.get_mem(0)
     }
 
-    /// Given the index of an instruction (opcode), call the given function and store
-    /// the result at the appropriate place.
-    fn eval_instruction<F: Fn(usize, usize) -> usize>(&mut self, index: usize, f: F) {
-        let i1 = self.ops[index + 1];
-        let i2 = self.ops[index + 2];
-        let target = self.ops[index + 3];
-        self.ops[target] = f(self.ops[i1], self.ops[i2]);
+    /// Evaluate the instructions contained in the computer, up to the first
+    /// halt instruction. Programs that ask for input fail with
+    /// `InputExhausted`, and ones that run on past `SEARCH_STEP_LIMIT` steps
+    /// are stopped.
+    pub fn eval(&mut self) -> Result<&IntcodeComputer, EvalError> {
+        match self.program.execute_limited(&[], Limits::new().steps(SEARCH_STEP_LIMIT))?.1 {
+            RunResult::Limited(reached) => Err(EvalError::Limited(reached)),
+            _                           => Ok(self)
+        }
     }
 
     /// Given a target, determine the inputs that must go at positions
     /// 1 and 2 in the instruction list to cause target to be stored at
     /// position 0.
     /// If no combination is found, returns None.
-    pub fn solve(&self, target: usize) -> Option<(usize, usize)> {
-
-        for i in 0..99 {
-            for j in 0..99 {
-                let mut working = self.clone();
-
-                let output = working
-                    .noun(i)
-                    .verb(j)
-                    .eval()
-                    .output();
+    ///
+    /// Position 0 is worked out as a formula in the noun and verb, which is
+    /// solved for the verb given each noun. If the program can't be analyzed
+    /// that way, every combination is tried instead.
+    pub fn solve(&self, target: isize) -> Option<(isize, isize)> {
+        let formula = analyze(&self.program, &[1, 2])
+            .ok()
+            .and_then(|analysis| analysis.cell(0))
+            .and_then(|formula| formula.linear_in(2));
+
+        match formula {
+            Some((a, b)) => (0..=99).find_map(|noun| IntcodeComputer::solve_verb(&a, &b, noun, target)),
+            None => search(&self.program, &[(1, 0..=99), (2, 0..=99)], 0, target)
+                .map(|values| (values[0], values[1]))
+        }
+    }
 
-                if output == target {
-                    return Some((i, j))
-                }
-            }
+    // Solve `a * verb + b = target` for a given noun.
+    fn solve_verb(a: &Poly, b: &Poly, noun: isize, target: isize) -> Option<(isize, isize)> {
+        let a = a.substitute(1, noun)?.as_constant()?;
+        let b = b.substitute(1, noun)?.as_constant()?;
 
+        let verb = match a {
+            0 if b == target           => 0,
+            0                          => return None,
+            _ if (target - b) % a != 0 => return None,
+            _                          => (target - b) / a
         };
-        None
+
+        if (0..=99).contains(&verb) {
+            Some((noun, verb))
+        } else {
+            None
+        }
     }
 }
 
@@ -96,33 +114,57 @@ mod tests {
     fn parse_works() {
         let i = IntcodeComputer::parse("1,9,10,3,2,3,11,0,99,30,40,50");
         assert_eq!(i.is_ok(), true);
-        assert_eq!(i.unwrap().ops, vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        assert_eq!(i.unwrap().ops(), vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
     }
 
     #[test]
     fn eval_works() {
         let mut i = IntcodeComputer::parse("1,9,10,3,2,3,11,0,99,30,40,50").expect("Parse failed");
-        i.eval();
+        i.eval().expect("Eval failed");
 
-        assert_eq!(i.ops, vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
+        assert_eq!(i.ops(), vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
 
         i = IntcodeComputer::parse("1,0,0,0,99").unwrap();
-        assert_eq!(i.eval().ops, vec![2, 0, 0, 0, 99]);
+        assert_eq!(i.eval().unwrap().ops(), vec![2, 0, 0, 0, 99]);
 
         i = IntcodeComputer::parse("2,3,0,3,99").unwrap();
-        assert_eq!(i.eval().ops, vec![2, 3, 0, 6, 99]);
+        assert_eq!(i.eval().unwrap().ops(), vec![2, 3, 0, 6, 99]);
 
         i = IntcodeComputer::parse("2,4,4,5,99,0").unwrap();
-        assert_eq!(i.eval().ops, vec![2, 4, 4, 5, 99, 9801]);
+        assert_eq!(i.eval().unwrap().ops(), vec![2, 4, 4, 5, 99, 9801]);
 
         i = IntcodeComputer::parse("1,1,1,4,99,5,6,0,99").unwrap();
-        assert_eq!(i.eval().ops, vec![30, 1, 1, 4, 2, 5, 6, 0, 99]);
+        assert_eq!(i.eval().unwrap().ops(), vec![30, 1, 1, 4, 2, 5, 6, 0, 99]);
+    }
+
+    #[test]
+    fn eval_rejects_invalid_opcode() {
+        let mut i = IntcodeComputer::parse("1,0,0,0,42").unwrap();
+        assert_eq!(i.eval().err(), Some(EvalError::Invalid(InvalidInstruction::InvalidOpcode { ip: 4, opcode: 42 })));
+
+        i = IntcodeComputer::parse("3,0,99").unwrap();
+        assert_eq!(i.eval().err(), Some(EvalError::Invalid(InvalidInstruction::InputExhausted { ip: 0 })));
+
+        i = IntcodeComputer::parse("1105,1,0").unwrap();
+        match i.eval() {
+            Err(EvalError::Limited(reached)) => assert_eq!(reached.steps, SEARCH_STEP_LIMIT),
+            other => panic!("expected a step limit, got {:?}", other)
+        };
+    }
+
+    #[test]
+    fn formula_depends_on_noun_and_verb() {
+        let cpu = IntcodeComputer::parse(INPUT).expect("Failed to parse input");
+        let analysis = analyze(&cpu.program, &[1, 2]).expect("Analysis failed");
+
+        assert_eq!(analysis.depends_on(0), Some(vec![1, 2].into_iter().collect()));
+        assert_eq!(analysis.cell(0).unwrap().degree(), 1);
     }
 
     #[test]
     fn part1_solution() {
         let mut i = IntcodeComputer::parse(INPUT).expect("Failed to parse input");
-        let output = i.noun(12).verb(2).eval().output();
+        let output = i.noun(12).verb(2).eval().expect("Eval failed").output();
 
         assert_eq!(output, 7210630);
     }
@@ -1,130 +1,287 @@
-use crate::intcode::{Program,Computer,InvalidInstruction,IOEvent};
+use crate::intcode::{Program,ProgramState,InvalidInstruction};
 
-use std::collections::HashMap;
-use std::sync::mpsc::{channel,Sender,Receiver};
-use std::thread;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self,BufRead,BufReader,BufWriter,Write};
+use std::path::Path;
 
 const INPUT : &'static str = include_str!("../inputs/day23.txt");
 
-struct NetworkNode {
-    id:   usize,
-    rx:   Receiver<isize>,
-    tx:   Sender<isize>
+const NAT : usize = 255;
+
+// Number of consecutive rounds without any packets being sent before the
+// network is considered idle.
+const IDLE_ROUNDS : usize = 2;
+
+// Give up on a network that never settles rather than running forever.
+const MAX_ROUNDS : usize = 100_000;
+
+// Most steps a node may take in one turn without blocking or halting.
+const MAX_TURN_STEPS : usize = 100_000;
+
+#[derive(Debug,Clone,PartialEq)]
+enum NetworkError {
+    Invalid(InvalidInstruction),
+    NegativeDestination { src: usize, dest: isize },
+    /// A packet was sent to an address that is neither a node nor the NAT.
+    UnknownDestination { src: usize, dest: usize },
+    /// A node ran for `MAX_TURN_STEPS` in one turn without asking for input.
+    StepLimit { node: usize },
+    /// The network ran for `MAX_ROUNDS` without the NAT repeating itself;
+    /// carries what was seen up to then.
+    RoundLimit(NatReport)
 }
 
-struct Network {
-    nodes:   Vec<NetworkNode>,
-    inputs:  HashMap<usize,Sender<isize>>,
-    program: Program
+impl From<InvalidInstruction> for NetworkError {
+    fn from(e: InvalidInstruction) -> NetworkError {
+        NetworkError::Invalid(e)
+    }
+}
+
+/// A packet as seen on the network. `time` is the round it was sent in; it is
+/// delivered to `dest` at the end of that round.
+#[derive(Debug,Clone,Copy,PartialEq)]
+struct Packet {
+    src:  usize,
+    dest: usize,
+    x:    isize,
+    y:    isize,
+    time: usize
+}
+
+impl Packet {
+    fn parse(line: &str) -> Option<Packet> {
+        let fields : Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+
+        Some(Packet {
+            time: fields[0].parse().ok()?,
+            src:  fields[1].parse().ok()?,
+            dest: fields[2].parse().ok()?,
+            x:    fields[3].parse().ok()?,
+            y:    fields[4].parse().ok()?
+        })
+    }
+}
+
+/// Write a capture as text, one `time src dest x y` line per packet.
+fn save_capture<P: AsRef<Path>>(packets: &[Packet], path: P) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+
+    writeln!(w, "# time src dest x y")?;
+    for p in packets {
+        writeln!(w, "{} {} {} {} {}", p.time, p.src, p.dest, p.x, p.y)?;
+    }
+    w.flush()
+}
+
+fn load_capture<P: AsRef<Path>>(path: P) -> io::Result<Vec<Packet>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| line.as_ref().map(|l| !l.trim().is_empty() && !l.starts_with('#')).unwrap_or(true))
+        .map(|line|{
+            let line = line?;
+            Packet::parse(&line)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid packet: {}", line)))
+        })
+        .collect()
+}
+
+struct NetworkNode {
+    id:      usize,
+    program: Program,
+    queue:   VecDeque<isize>,
+    pending: Vec<isize>
 }
 
 impl NetworkNode {
-    fn new(id: usize) -> NetworkNode {
-        let (tx, rx) = channel();
+    fn new(id: usize, program: &Program) -> NetworkNode {
+        // each node receives its own address as its first input
         NetworkNode {
-            id:   id,
-            rx:   rx,
-            tx:   tx
+            id,
+            program: program.clone(),
+            queue:   vec![id as isize].into(),
+            pending: vec![]
         }
     }
+
+    fn receive(&mut self, x: isize, y: isize) {
+        self.queue.push_back(x);
+        self.queue.push_back(y);
+    }
+
+    // Run until the node halts or asks for input when its queue is empty, in
+    // which case it's given -1. Returns the packets sent, stamped with `time`.
+    fn run(&mut self, time: usize) -> Result<Vec<Packet>, NetworkError> {
+        let mut sent = vec![];
+        let mut next_input = None;
+
+        for _ in 0..MAX_TURN_STEPS {
+            let state = self.program.step(next_input)?;
+            next_input = None;
+
+            match state {
+                ProgramState::Halted => return Ok(sent),
+                ProgramState::Running(None) => {},
+                ProgramState::Running(Some(v)) => {
+                    self.pending.push(v);
+                    if self.pending.len() == 3 {
+                        let dest = self.pending[0];
+                        if dest < 0 {
+                            return Err(NetworkError::NegativeDestination { src: self.id, dest });
+                        }
+                        sent.push(Packet {
+                            src:  self.id,
+                            dest: dest as usize,
+                            x:    self.pending[1],
+                            y:    self.pending[2],
+                            time
+                        });
+                        self.pending.clear();
+                    }
+                },
+                ProgramState::Blocked => match self.queue.pop_front() {
+                    Some(v) => next_input = Some(v),
+                    None    => {
+                        self.program.step(Some(-1))?;
+                        return Ok(sent);
+                    }
+                }
+            };
+        }
+
+        Err(NetworkError::StepLimit { node: self.id })
+    }
 }
 
-enum OutputState {
-    Address,
-    X,
-    Y
+#[derive(Debug,Clone,Copy,PartialEq,Default)]
+struct NatReport {
+    // the Y value of the first packet sent to the NAT
+    first_y:    Option<isize>,
+    // the first Y value the NAT delivers to node 0 twice in a row
+    repeated_y: Option<isize>
+}
+
+struct Network {
+    nodes:   Vec<NetworkNode>,
+    nat:     Option<(isize, isize)>,
+    capture: Option<Vec<Packet>>
 }
 
 impl Network {
     fn new(count: usize, p: &Program) -> Network {
-        let nodes : Vec<NetworkNode> = (0..count)
-            .map(|id| NetworkNode::new(id))
-            .collect();
+        Network {
+            nodes:   (0..count).map(|id| NetworkNode::new(id, p)).collect(),
+            nat:     None,
+            capture: None
+        }
+    }
 
-        let inputs = nodes
-            .iter()
-            .map(|node| (node.id, node.tx.clone()))
-            .collect();
+    // Record every packet from now on, including those sent by the NAT.
+    fn start_capture(&mut self) {
+        self.capture = Some(vec![]);
+    }
 
-        Network {
-            nodes:   nodes,
-            inputs:  inputs,
-            program: p.clone()
+    fn captured(&self) -> &[Packet] {
+        self.capture.as_ref().map(|c| &c[..]).unwrap_or(&[])
+    }
+
+    fn send(&mut self, packet: Packet) -> Result<(), NetworkError> {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.push(packet);
+        }
+
+        if packet.dest == NAT {
+            self.nat = Some((packet.x, packet.y));
+        } else if let Some(node) = self.nodes.get_mut(packet.dest) {
+            node.receive(packet.x, packet.y);
+        } else {
+            return Err(NetworkError::UnknownDestination { src: packet.src, dest: packet.dest });
         }
+
+        Ok(())
     }
 
-    fn run(mut self) {
-        self.boot();
-
-        let inputs  = self.inputs.clone();
-        let program = self.program.clone();
-
-        let handles : Vec<thread::JoinHandle<_>> = self.nodes
-            .drain(..)
-            .map(|node|{
-                let map = inputs.clone();
-                let program = program.clone();
-
-                thread::spawn(move ||{
-                    let n = node;
-                    let inputs = map;
-                    let mut output_state = OutputState::Address;
-                    let mut addr = 0;
-                    let mut x = 0;
-                    let mut y = 0;
-                    let mut p = program;
-                    Computer::run(&mut p, |event, _|{
-                        match event {
-                            IOEvent::Output(value) => {
-                                match output_state {
-                                    OutputState::Address => {
-                                        addr = value;
-                                        output_state = OutputState::X;
-                                    },
-                                    OutputState::X => {
-                                        x = value;
-                                        output_state = OutputState::Y;
-                                    },
-                                    OutputState::Y => {
-                                        y = value;
-                                        output_state = OutputState::Address;
-                                        match inputs.get(&(addr as usize)) {
-                                            Some(tx) => {
-                                                tx.send(x).unwrap();
-                                                tx.send(y).unwrap();
-                                            },
-                                            None => {
-                                                println!("attempted to send {},{} to non-existant address {}", x, y, addr);
-                                            }
-                                        };
-                                    }
-                                };
-                                None
-                            },
-                            IOEvent::Input => {
-                                let next = n.rx.try_recv().unwrap_or(-1);
-                                Some(next)
-                            }
-                        }
-                    });
-                })
-            })
-            .collect();
+    // Give every node a turn, then deliver the packets sent during the round.
+    fn round(&mut self, time: usize) -> Result<Vec<Packet>, NetworkError> {
+        let mut sent = vec![];
+        for node in self.nodes.iter_mut() {
+            sent.extend(node.run(time)?);
+        }
 
-        for h in handles {
-            h.join().unwrap();
-        };
+        for &packet in &sent {
+            self.send(packet)?;
+        }
+
+        Ok(sent)
     }
 
-    fn boot(&mut self) {
-        // send each node it's own ID as input to boot up
-        self
-            .inputs
-            .iter()
-            .for_each(|(id, input)|{
-                input.send(*id as isize).unwrap();
-            });
+    fn halted(&self) -> bool {
+        self.nodes.iter().all(|n| n.program.state() == ProgramState::Halted)
+    }
+
+    // Run until the NAT delivers the same Y value twice in a row or the
+    // network halts. Fails if neither happens within MAX_ROUNDS.
+    fn run(&mut self) -> Result<NatReport, NetworkError> {
+        let mut report = NatReport::default();
+        let mut last_delivered = None;
+        let mut quiet = 0;
+
+        for time in 0..MAX_ROUNDS {
+            let sent = self.round(time)?;
+
+            if report.first_y.is_none() {
+                report.first_y = sent.iter().find(|p| p.dest == NAT).map(|p| p.y);
+            }
+
+            quiet = if sent.is_empty() { quiet + 1 } else { 0 };
+            if self.halted() {
+                return Ok(report);
+            }
+            if quiet < IDLE_ROUNDS {
+                continue;
+            }
+
+            // nodes can sit idle for a while after booting, before anything
+            // has been sent to the NAT
+            let (x, y) = match self.nat {
+                Some(packet) => packet,
+                None         => continue
+            };
+            if last_delivered == Some(y) {
+                report.repeated_y = Some(y);
+                return Ok(report);
+            }
+
+            last_delivered = Some(y);
+            self.send(Packet { src: NAT, dest: 0, x, y, time })?;
+            quiet = 0;
+        }
+
+        Err(NetworkError::RoundLimit(report))
+    }
+}
+
+/// Run a single node in isolation, feeding it the packets addressed to it in
+/// `capture` at the same points they were delivered in the original run.
+/// Returns the packets the node sent, which should match those it sent in
+/// the capture.
+fn replay(id: usize, program: &Program, capture: &[Packet]) -> Result<Vec<Packet>, NetworkError> {
+    let mut node = NetworkNode::new(id, program);
+    let rounds = capture.iter().map(|p| p.time + 1).max().unwrap_or(0);
+    let mut sent = vec![];
+
+    for time in 0..rounds {
+        sent.extend(node.run(time)?);
+
+        for p in capture.iter().filter(|p| p.dest == id && p.time == time) {
+            node.receive(p.x, p.y);
+        }
     }
+
+    Ok(sent)
 }
 
 
@@ -135,8 +292,58 @@ mod test {
     #[test]
     fn p1_solution() {
         let p = Program::parse(INPUT).expect("Failed to parse program");
-        let net = Network::new(50, &p);
+        let mut net = Network::new(50, &p);
 
-        net.run();
+        let report = net.run().expect("Network failed");
+        assert_eq!(report.first_y, Some(17283));
+    }
+
+    #[test]
+    fn p2_solution() {
+        let p = Program::parse(INPUT).expect("Failed to parse program");
+        let mut net = Network::new(50, &p);
+
+        let report = net.run().expect("Network failed");
+        assert_eq!(report.repeated_y, Some(11319));
+    }
+
+    #[test]
+    fn network_errors() {
+        // send a packet to -1
+        let p = Program::parse("104,-1,104,1,104,2,99").unwrap();
+        assert_eq!(Network::new(2, &p).run(), Err(NetworkError::NegativeDestination { src: 0, dest: -1 }));
+
+        // send a packet to a node that doesn't exist
+        let p = Program::parse("104,5,104,1,104,2,99").unwrap();
+        assert_eq!(Network::new(2, &p).run(), Err(NetworkError::UnknownDestination { src: 0, dest: 5 }));
+
+        // read input forever without sending anything
+        let p = Program::parse("3,10,1105,1,0").unwrap();
+        assert_eq!(Network::new(2, &p).run(), Err(NetworkError::RoundLimit(NatReport::default())));
+
+        // loop forever without reading input
+        let p = Program::parse("1105,1,0").unwrap();
+        assert_eq!(Network::new(2, &p).run(), Err(NetworkError::StepLimit { node: 0 }));
+    }
+
+    #[test]
+    fn capture_and_replay() {
+        let p = Program::parse(INPUT).expect("Failed to parse program");
+        let mut net = Network::new(50, &p);
+        net.start_capture();
+        net.run().expect("Network failed");
+
+        let path = std::env::temp_dir().join(format!("aoc-day23-capture_and_replay-{}.txt", std::process::id()));
+        save_capture(net.captured(), &path).expect("Failed to save capture");
+        let capture = load_capture(&path).expect("Failed to load capture");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(capture, net.captured());
+
+        // node 0 also receives the packets sent by the NAT
+        for &id in &[0, 17] {
+            let expected : Vec<Packet> = capture.iter().filter(|p| p.src == id).cloned().collect();
+            assert!(!expected.is_empty());
+            assert_eq!(replay(id, &p, &capture).unwrap(), expected);
+        }
     }
 }
@@ -2,9 +2,30 @@ const INPUT : &'static str = include_str!("../inputs/day7.txt");
 
 use crate::day5::{Program,ProgramState};
 use crate::day5::InvalidInstruction;
+use crate::intcode::{Topology,SeedMismatch};
 
 use itertools::Itertools;
-use std::collections::VecDeque;
+
+#[derive(Debug,Clone,PartialEq)]
+enum AmpError {
+    Invalid(InvalidInstruction),
+    /// The phases given don't match the number of amps.
+    Phases(SeedMismatch),
+    /// The last amp never produced a signal.
+    NoOutput
+}
+
+impl From<InvalidInstruction> for AmpError {
+    fn from(e: InvalidInstruction) -> AmpError {
+        AmpError::Invalid(e)
+    }
+}
+
+impl From<SeedMismatch> for AmpError {
+    fn from(e: SeedMismatch) -> AmpError {
+        AmpError::Phases(e)
+    }
+}
 
 #[derive(Debug,Clone)]
 struct Amp {
@@ -17,34 +38,31 @@ impl Amp {
         Amp { program: program, program_state: ProgramState::Running(None) }
     }
 
-    pub fn execute(&self, phase: isize, input: isize) -> Result<Vec<isize>, InvalidInstruction> {
-        let mut p = self.program.clone();
-
-        p.execute(&vec![phase, input])
-    }
-
-    pub fn step_mut(&mut self, input: Option<isize>) -> Result<ProgramState, InvalidInstruction> {
-        let rc = self.program.step(input);
-        if let Ok(state) = rc {
-            self.program_state = state;
-        }
-        rc
-    }
-
     pub fn new_array(program: &Program, count: usize) -> Vec<Amp> {
         (0..count)
             .map(|_| Amp::new(program.clone()) )
             .collect()
     }
 
-    pub fn exec_array(amps: &Vec<Amp>, phases: &Vec<isize>) -> Result<isize, InvalidInstruction> {
-        let mut input = 0;
-        for i in 0..amps.len() {
-            let output = amps[i].execute(phases[i], input)?;
-            input = output[0];
-        }
+    // Each amp is seeded with its phase setting, and the first also with the
+    // initial input signal of 0.
+    fn seeds(phases: &[isize]) -> Vec<Vec<isize>> {
+        phases
+            .iter()
+            .enumerate()
+            .map(|(i, &phase)| if i == 0 { vec![phase, 0] } else { vec![phase] })
+            .collect()
+    }
 
-        Ok(input)
+    fn programs(amps: &[Amp]) -> Vec<Program> {
+        amps.iter().map(|amp| amp.program.clone()).collect()
+    }
+
+    pub fn exec_array(amps: &Vec<Amp>, phases: &Vec<isize>) -> Result<isize, AmpError> {
+        let mut chain = Topology::chain(Amp::programs(amps), Amp::seeds(phases))?;
+        let outputs = chain.run()?;
+
+        outputs.last().and_then(|o| o.first()).cloned().ok_or(AmpError::NoOutput)
     }
 
     pub fn best_output(amps: &Vec<Amp>) -> isize {
@@ -66,49 +84,16 @@ impl Amp {
             .unwrap()
     }
     
-    pub fn exec_array_feedback(amps: &mut Vec<Amp>, phases: &Vec<isize>) -> Result<isize, InvalidInstruction> {
-        let mut outputs : Vec<VecDeque<isize>> = (0..amps.len())
-            .map(|i|{
-                let mut q = VecDeque::new();
-                let next = (i + 1) % amps.len();
-                q.push_back(phases[next]);
-                q
-            })
-            .collect();
-
-        // input to Amp 1 is phase, then 0.
-        outputs[amps.len() - 1].push_back(0);
-        let mut done;
-
-        loop {
-            done = true;
-            for i in 0..amps.len() {
-                let prev = if i == 0 { amps.len() - 1 } else { i - 1 };
-
-                match amps[i].program_state {
-                    ProgramState::Blocked => {
-                        if let ProgramState::Running(Some(v)) = amps[i].step_mut(outputs[prev].pop_front())? {
-                            outputs[i].push_back(v);
-                        }
-                        done = false;
-                    },
-                    ProgramState::Running(_) => {
-                        if let ProgramState::Running(Some(v)) = amps[i].step_mut(None)? {
-                            outputs[i].push_back(v);
-                        }
-                        done = false;
-                    }
-                    ProgramState::Halted => {
-                    }
-                };
-
-            }
-
-            if done { break }
+    pub fn exec_array_feedback(amps: &mut Vec<Amp>, phases: &Vec<isize>) -> Result<isize, AmpError> {
+        let mut ring = Topology::ring(Amp::programs(amps), Amp::seeds(phases))?;
+        let outputs = ring.run()?;
+
+        for (i, amp) in amps.iter_mut().enumerate() {
+            amp.program = ring.program(i).clone();
+            amp.program_state = amp.program.state();
         }
 
-        let result = *outputs.last().map(|o| o.back().unwrap()).unwrap();
-        Ok(result)
+        outputs.last().and_then(|o| o.last()).cloned().ok_or(AmpError::NoOutput)
     }
 }
 
@@ -130,7 +115,11 @@ mod tests {
         let amps = Amp::new_array(&p, 5);
 
         assert_eq!(Amp::exec_array(&amps, &vec![0, 1, 2, 3, 4]), Ok(54321));
+        assert_eq!(Amp::exec_array(&amps, &vec![0, 1]), Err(AmpError::Phases(SeedMismatch { programs: 5, seeds: 2 })));
 
+        // the amps halt without output
+        let p = Program::parse("3,0,99").unwrap();
+        assert_eq!(Amp::exec_array(&Amp::new_array(&p, 5), &vec![0, 1, 2, 3, 4]), Err(AmpError::NoOutput));
     }
 
     #[test]
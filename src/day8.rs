@@ -55,6 +55,7 @@ pub fn collapse_layers(layers: &Vec<Vec<usize>>) -> Vec<usize> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ocr::Grid;
 
     #[test]
     fn p1_examples() {
@@ -94,6 +95,6 @@ mod tests {
         let layers = decode_layers(INPUT, 25, 6);
         let layer = collapse_layers(&layers);
 
-        print_layer(&layer, 25, 6);
+        assert_eq!(Grid::from_layer(&layer, 25).unwrap().read(), Ok("PFCAK".to_string()));
     }
 }
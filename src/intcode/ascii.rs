@@ -0,0 +1,84 @@
+use super::{Program,Computer,IOEvent,InvalidInstruction};
+
+/// Everything an ASCII program printed. Output values outside the ASCII
+/// range, like a final answer, are kept separately in the order they appeared.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct AsciiOutput {
+    pub text:   String,
+    pub values: Vec<isize>
+}
+
+impl AsciiOutput {
+    fn push(&mut self, value: isize) {
+        if (0..128).contains(&value) {
+            self.text.push(value as u8 as char);
+        } else {
+            self.values.push(value);
+        }
+    }
+}
+
+/// Encode text as program input, adding a trailing newline if it's missing.
+pub fn encode(text: &str) -> Vec<isize> {
+    let mut values : Vec<isize> = text.chars().map(|c| c as isize).collect();
+    if !text.is_empty() && !text.ends_with('\n') {
+        values.push('\n' as isize);
+    }
+    values
+}
+
+/// Run a program that talks in ASCII, feeding it `input` (see `encode`)
+/// and collecting what it prints.
+///
+/// If the program asks for more input than it was given, it is stopped and
+/// left blocked, so it can be resumed with another call, e.g. to answer the
+/// next prompt of a text adventure.
+pub fn run_ascii(program: &mut Program, input: &str) -> Result<AsciiOutput, InvalidInstruction> {
+    let mut input = encode(input).into_iter();
+    let mut output = AsciiOutput::default();
+
+    Computer::run(program, |event, computer|{
+        match event {
+            IOEvent::Input => {
+                let value = input.next();
+                if value.is_none() {
+                    computer.abort();
+                }
+                value
+            },
+            IOEvent::Output(v) => {
+                output.push(v);
+                None
+            }
+        }
+    })?;
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::ProgramState;
+
+    #[test]
+    fn encode_terminates_lines() {
+        assert_eq!(encode("A,B"), vec![65, 44, 66, 10]);
+        assert_eq!(encode("n\n"), vec![110, 10]);
+        assert_eq!(encode(""), vec![]);
+    }
+
+    #[test]
+    fn run_and_resume() {
+        // echo two characters, print a large number, then echo one more
+        let mut p = Program::parse("3,20,4,20,3,20,4,20,104,1000,3,20,4,20,99").unwrap();
+
+        let output = run_ascii(&mut p, "a").unwrap();
+        assert_eq!(output, AsciiOutput { text: "a\n".to_string(), values: vec![1000] });
+        assert_eq!(p.state(), ProgramState::Blocked);
+
+        let output = run_ascii(&mut p, "b").unwrap();
+        assert_eq!(output.text, "b");
+        assert_eq!(p.state(), ProgramState::Halted);
+    }
+}
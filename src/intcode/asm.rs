@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use super::instruction::*;
+
+/// Why a line of assembly was rejected.
+#[derive(Debug,Clone,PartialEq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    InvalidOperand(String),
+    OperandCount { expected: usize, found: usize },
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    AttemptedImmediateLoad
+}
+
+/// An assembly error, located by 1-based line and column.
+#[derive(Debug,Clone,PartialEq)]
+pub struct AsmError {
+    pub line:   usize,
+    pub column: usize,
+    pub kind:   AsmErrorKind
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}:{}: {:?}", self.line, self.column, self.kind)
+    }
+}
+
+impl Error for AsmError {}
+
+// A value that may refer to a label, e.g. `12`, `loop` or `loop+3`.
+#[derive(Debug,Clone)]
+enum Expr {
+    Number(isize),
+    Label(String, isize)
+}
+
+// An operand or data word along with the column it started at. Bare values
+// without a mode marker are only accepted by `.data`.
+#[derive(Debug,Clone)]
+struct Word {
+    mode:   Option<AddressMode>,
+    expr:   Expr,
+    column: usize,
+    text:   String
+}
+
+#[derive(Debug)]
+enum Item {
+    Code { opcode: OpCode, args: Vec<Word> },
+    Data(Vec<Word>)
+}
+
+#[derive(Debug)]
+struct Statement {
+    line: usize,
+    item: Item
+}
+
+/// Assemble source into the comma separated format read by `Program::parse`.
+///
+/// Each line holds an optional `label:`, then either an instruction such as
+/// `add [12], #5, rb+3` or a `.data 1, 2, label` directive. Operands are
+/// written `[x]` for position mode, `#x` for immediate mode and `rb+x` or
+/// `rb-n` for relative mode, where `x` is a number, a label, or `label+n`.
+/// Anything after a `;` is a comment.
+pub fn assemble(source: &str) -> Result<String, AsmError> {
+    let words = assemble_words(source)?;
+    let parts : Vec<String> = words.iter().map(|w| w.to_string()).collect();
+
+    Ok(parts.join(","))
+}
+
+/// Assemble source into a list of memory values.
+pub fn assemble_words(source: &str) -> Result<Vec<isize>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = vec![];
+    let mut addr = 0;
+
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let text = raw.split(';').next().unwrap();
+        let mut column = 0;
+        let mut rest = text;
+
+        if let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if !is_identifier(name) {
+                return Err(AsmError { line, column: leading(text) + 1, kind: AsmErrorKind::InvalidOperand(name.to_string()) });
+            }
+            if labels.insert(name.to_string(), addr).is_some() {
+                return Err(AsmError { line, column: leading(text) + 1, kind: AsmErrorKind::DuplicateLabel(name.to_string()) });
+            }
+            column = colon + 1;
+            rest = &text[colon + 1..];
+        }
+
+        if rest.trim().is_empty() {
+            continue;
+        }
+
+        column += leading(rest);
+        let rest = rest.trim_start();
+        let (name, operands) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None    => (rest, "")
+        };
+        let operand_column = column + name.len();
+        let args = parse_words(operands, operand_column, line)?;
+
+        let item = if name == ".data" {
+            if let Some(w) = args.iter().find(|w| w.mode.is_some()) {
+                return Err(AsmError { line, column: w.column, kind: AsmErrorKind::InvalidOperand(w.text.clone()) });
+            }
+            Item::Data(args)
+        } else {
+            let opcode = OpCode::from_mnemonic(name).ok_or(AsmError {
+                line, column: column + 1, kind: AsmErrorKind::UnknownMnemonic(name.to_string())
+            })?;
+
+            if let Some(w) = args.iter().find(|w| w.mode.is_none()) {
+                return Err(AsmError { line, column: w.column, kind: AsmErrorKind::InvalidOperand(w.text.clone()) });
+            }
+
+            if args.len() != opcode.arity() {
+                return Err(AsmError {
+                    line, column: column + 1,
+                    kind: AsmErrorKind::OperandCount { expected: opcode.arity(), found: args.len() }
+                });
+            }
+
+            if let Some(w) = opcode.write_operand() {
+                if args[w].mode == Some(AddressMode::Immediate) {
+                    return Err(AsmError { line, column: args[w].column, kind: AsmErrorKind::AttemptedImmediateLoad });
+                }
+            }
+
+            Item::Code { opcode, args }
+        };
+
+        addr += match &item {
+            Item::Code { args, .. } => args.len() + 1,
+            Item::Data(args)        => args.len()
+        };
+        statements.push(Statement { line, item });
+    }
+
+    let mut output = vec![];
+    for statement in statements {
+        match statement.item {
+            Item::Code { opcode, args } => {
+                let modes = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| (w.mode.unwrap() as isize) * 10_isize.pow(i as u32 + 2))
+                    .sum::<isize>();
+
+                output.push(opcode as isize + modes);
+                for w in &args {
+                    output.push(resolve(w, &labels, statement.line)?);
+                }
+            },
+            Item::Data(args) => {
+                for w in &args {
+                    output.push(resolve(w, &labels, statement.line)?);
+                }
+            }
+        };
+    }
+
+    Ok(output)
+}
+
+fn resolve(word: &Word, labels: &HashMap<String, usize>, line: usize) -> Result<isize, AsmError> {
+    match &word.expr {
+        Expr::Number(n) => Ok(*n),
+        Expr::Label(name, offset) => {
+            labels
+                .get(name)
+                .map(|&addr| addr as isize + offset)
+                .ok_or(AsmError { line, column: word.column, kind: AsmErrorKind::UndefinedLabel(name.clone()) })
+        }
+    }
+}
+
+// Split a comma separated operand list, tracking the 1-based column of each.
+fn parse_words(text: &str, column: usize, line: usize) -> Result<Vec<Word>, AsmError> {
+    if text.trim().is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut words = vec![];
+    let mut offset = column;
+
+    for part in text.split(',') {
+        let start = offset + leading(part) + 1;
+        let word = parse_word(part.trim())
+            .ok_or(AsmError { line, column: start, kind: AsmErrorKind::InvalidOperand(part.trim().to_string()) })?;
+
+        words.push(Word { column: start, text: part.trim().to_string(), ..word });
+        offset += part.len() + 1;
+    }
+
+    Ok(words)
+}
+
+fn parse_word(text: &str) -> Option<Word> {
+    let (mode, expr) = if text.starts_with('[') && text.ends_with(']') {
+        (Some(AddressMode::Position), parse_expr(&text[1..text.len() - 1])?)
+    } else if let Some(rest) = text.strip_prefix('#') {
+        (Some(AddressMode::Immediate), parse_expr(rest)?)
+    } else if let Some(expr) = text.strip_prefix("rb").and_then(parse_relative) {
+        (Some(AddressMode::Relative), expr)
+    } else {
+        (None, parse_expr(text)?)
+    };
+
+    Some(Word { mode, expr, column: 0, text: String::new() })
+}
+
+fn parse_expr(text: &str) -> Option<Expr> {
+    let text = text.trim();
+    if let Some(n) = parse_number(text) {
+        return Some(Expr::Number(n));
+    }
+
+    match text.find(['+', '-']) {
+        Some(i) => {
+            let name = text[..i].trim();
+            let offset = parse_number(&text[i..])?;
+            if is_identifier(name) { Some(Expr::Label(name.to_string(), offset)) } else { None }
+        },
+        None => {
+            if is_identifier(text) { Some(Expr::Label(text.to_string(), 0)) } else { None }
+        }
+    }
+}
+
+// The offset following `rb`: `+n`, `-n`, `+label` or `+label+n`.
+fn parse_relative(text: &str) -> Option<Expr> {
+    let text = text.trim_start();
+    match text.strip_prefix('+') {
+        Some(rest) => parse_expr(rest),
+        None       => parse_number(text).map(Expr::Number)
+    }
+}
+
+// A number with an optional sign, which may be followed by spaces.
+fn parse_number(text: &str) -> Option<isize> {
+    let text = text.trim();
+    let (negative, digits) = match text.chars().next() {
+        Some('+') => (false, text[1..].trim_start()),
+        Some('-') => (true, text[1..].trim_start()),
+        _         => (false, text)
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    if negative { format!("-{}", digits).parse().ok() } else { digits.parse().ok() }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+fn leading(text: &str) -> usize {
+    text.len() - text.trim_start().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Program,disassemble};
+
+    #[test]
+    fn assemble_basic() {
+        assert_eq!(assemble("mul [4], #3, [4]\n.data 33"), Ok("1002,4,3,4,33".to_string()));
+    }
+
+    #[test]
+    fn assemble_labels() {
+        let source = "
+            ; count down from 3, printing each value
+            loop: out [counter]
+                  add [counter], #-1, [counter]
+                  jt  [counter], #loop
+                  hlt
+            counter: .data 3
+        ";
+
+        let program = assemble(source).unwrap();
+        assert_eq!(program, "4,10,1001,10,-1,10,1005,10,0,99,3");
+
+        let mut p = Program::parse(&program).unwrap();
+        assert_eq!(p.execute(&vec![]), Ok(vec![3, 2, 1]));
+    }
+
+    #[test]
+    fn assemble_relative() {
+        let program = assemble("rbo #end\nout rb-1\nhlt\n.data 42\nend:").unwrap();
+        let mut p = Program::parse(&program).unwrap();
+
+        assert_eq!(p.execute(&vec![]), Ok(vec![42]));
+
+        let program = assemble("out rb+value\nout rb + value+1\nhlt\nvalue: .data 7, 8").unwrap();
+        assert_eq!(program, "204,5,204,6,99,7,8");
+    }
+
+    #[test]
+    fn assemble_round_trip() {
+        let source = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let p = Program::parse(source).unwrap();
+
+        let text : Vec<String> = disassemble(&p).iter().map(|l| l.to_string()).collect();
+        assert_eq!(assemble(&text.join("\n")), Ok(source.to_string()));
+    }
+
+    #[test]
+    fn assemble_errors() {
+        assert_eq!(assemble("add #1, #2, [3]\nfoo [1]"), Err(AsmError {
+            line: 2, column: 1, kind: AsmErrorKind::UnknownMnemonic("foo".to_string())
+        }));
+
+        assert_eq!(assemble("  add #1, #2, #3"), Err(AsmError {
+            line: 1, column: 15, kind: AsmErrorKind::AttemptedImmediateLoad
+        }));
+
+        assert_eq!(assemble("jt #1, #nowhere"), Err(AsmError {
+            line: 1, column: 8, kind: AsmErrorKind::UndefinedLabel("nowhere".to_string())
+        }));
+
+        assert_eq!(assemble("out [1], [2]"), Err(AsmError {
+            line: 1, column: 1, kind: AsmErrorKind::OperandCount { expected: 1, found: 2 }
+        }));
+
+        assert_eq!(assemble("x: .data 1
+out x"), Err(AsmError {
+            line: 2, column: 5, kind: AsmErrorKind::InvalidOperand("x".to_string())
+        }));
+
+        assert_eq!(assemble(".data 1 2"), Err(AsmError {
+            line: 1, column: 7, kind: AsmErrorKind::InvalidOperand("1 2".to_string())
+        }));
+
+        assert_eq!(assemble("out #1 2"), Err(AsmError {
+            line: 1, column: 5, kind: AsmErrorKind::InvalidOperand("#1 2".to_string())
+        }));
+
+        assert_eq!(assemble(".data 1, #2"), Err(AsmError {
+            line: 1, column: 10, kind: AsmErrorKind::InvalidOperand("#2".to_string())
+        }));
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap,BTreeSet};
+
+use super::{Program,Line,OpCode,AddressMode};
+use super::disasm::decode_at;
+
+/// How control leaves a basic block.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Exit {
+    /// Runs straight into the next block.
+    Fallthrough,
+    /// Ends in a jump whose target is an immediate.
+    Jump,
+    /// Ends in a jump whose target is only known at runtime.
+    Indirect,
+    Halt,
+    /// Runs into memory that doesn't decode as an instruction.
+    Invalid
+}
+
+/// A run of instructions that is only ever entered at the top.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Block {
+    pub start:      usize,
+    pub lines:      Vec<Line>,
+    pub successors: Vec<usize>,
+    pub exit:       Exit
+}
+
+/// The control flow graph of the code reachable from a program's current
+/// instruction pointer, found by following fallthroughs and immediate jump
+/// targets. Code only reachable through indirect jumps is not included.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Cfg {
+    pub entry:  usize,
+    pub blocks: BTreeMap<usize, Block>,
+    /// Instructions that write into reachable code, as (instruction address,
+    /// address written). Only position mode targets are known statically.
+    pub self_modifying: Vec<(usize, usize)>
+}
+
+// The addresses control can move to after `line`, whether it can fall through
+// to the next instruction, and how it ends a block if it does.
+fn flow(line: &Line) -> (Vec<usize>, bool, Exit) {
+    let (instr, args) = match line {
+        Line::Code { instr, args, .. } => (instr, args),
+        Line::Data { .. } => return (vec![], false, Exit::Invalid)
+    };
+
+    let taken = match instr.opcode {
+        OpCode::Halt     => return (vec![], false, Exit::Halt),
+        OpCode::JmpTrue  => args[0].value != 0,
+        OpCode::JmpFalse => args[0].value == 0,
+        _                => return (vec![], true, Exit::Fallthrough)
+    };
+
+    // with an immediate condition the jump always or never happens
+    let (jumps, falls) = match args[0].mode {
+        AddressMode::Immediate => (taken, !taken),
+        _                      => (true, true)
+    };
+
+    let target = args[1];
+    match (jumps, target.mode) {
+        (false, _) => (vec![], true, Exit::Fallthrough),
+        (true, AddressMode::Immediate) if target.value >= 0 => (vec![target.value as usize], falls, Exit::Jump),
+        (true, AddressMode::Immediate) => (vec![], falls, Exit::Invalid),
+        (true, _)                      => (vec![], falls, Exit::Indirect)
+    }
+}
+
+impl Cfg {
+    pub fn build(program: &Program) -> Cfg {
+        let size = program.size();
+        let entry = program.ip();
+
+        // every reachable address, and the line there if it decodes
+        let mut lines : BTreeMap<usize, Option<Line>> = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut work = vec![entry];
+        leaders.insert(entry);
+
+        while let Some(addr) = work.pop() {
+            if lines.contains_key(&addr) {
+                continue;
+            }
+
+            let line = decode_at(program, addr, size);
+            if let Some(line) = &line {
+                let (targets, falls, exit) = flow(line);
+                let next = addr + line.width();
+
+                if exit != Exit::Fallthrough {
+                    leaders.extend(targets.iter().cloned());
+                    if falls {
+                        leaders.insert(next);
+                    }
+                }
+
+                work.extend(targets);
+                if falls {
+                    work.push(next);
+                }
+            }
+            lines.insert(addr, line);
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut block = Block { start, lines: vec![], successors: vec![], exit: Exit::Invalid };
+            let mut addr = start;
+
+            while let Some(Some(line)) = lines.get(&addr) {
+                let (targets, falls, exit) = flow(line);
+                block.lines.push(line.clone());
+                addr += line.width();
+
+                if exit != Exit::Fallthrough {
+                    block.successors = targets;
+                    if falls {
+                        block.successors.push(addr);
+                    }
+                    block.exit = exit;
+                    break;
+                }
+
+                if leaders.contains(&addr) {
+                    block.successors = vec![addr];
+                    block.exit = Exit::Fallthrough;
+                    break;
+                }
+            }
+
+            blocks.insert(start, block);
+        }
+
+        let code : BTreeSet<usize> = lines
+            .values()
+            .flatten()
+            .flat_map(|line| line.addr()..line.addr() + line.width())
+            .collect();
+
+        let self_modifying = lines
+            .values()
+            .flatten()
+            .filter_map(|line| match line {
+                Line::Code { addr, instr, args } => {
+                    let target = args[instr.opcode.write_operand()?];
+                    if target.mode == AddressMode::Position && target.value >= 0 && code.contains(&(target.value as usize)) {
+                        Some((*addr, target.value as usize))
+                    } else {
+                        None
+                    }
+                },
+                _ => None
+            })
+            .collect();
+
+        Cfg { entry, blocks, self_modifying }
+    }
+
+    /// The block containing the memory cell at `addr`.
+    pub fn block_at(&self, addr: usize) -> Option<&Block> {
+        self.blocks
+            .values()
+            .find(|b| b.lines.iter().any(|l| l.addr() <= addr && addr < l.addr() + l.width()))
+    }
+
+    /// Render the graph in Graphviz DOT format. Halting blocks are drawn with
+    /// a double border, indirect jumps as a dashed edge to a `?` node, and
+    /// writes into code as dotted red edges.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        let mut indirect = false;
+
+        for block in self.blocks.values() {
+            let mut label : String = block.lines
+                .iter()
+                .map(|line| format!("{}: {}\\l", line.addr(), line))
+                .collect();
+            if block.exit == Exit::Invalid {
+                label.push_str("(invalid)\\l");
+            }
+
+            let style = match block.exit {
+                Exit::Halt    => ", peripheries=2",
+                Exit::Invalid => ", color=red",
+                _             => ""
+            };
+            dot.push_str(&format!("    b{} [label=\"{}\"{}];\n", block.start, label, style));
+
+            for s in &block.successors {
+                dot.push_str(&format!("    b{} -> b{};\n", block.start, s));
+            }
+            if block.exit == Exit::Indirect {
+                dot.push_str(&format!("    b{} -> indirect [style=dashed];\n", block.start));
+                indirect = true;
+            }
+        }
+
+        if indirect {
+            dot.push_str("    indirect [label=\"?\", shape=circle];\n");
+        }
+
+        for &(from, to) in &self.self_modifying {
+            if let (Some(a), Some(b)) = (self.block_at(from), self.block_at(to)) {
+                dot.push_str(&format!("    b{} -> b{} [style=dotted, color=red, label=\"writes {}\"];\n", a.start, b.start, to));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_and_jumps() {
+        //  0: in  [20]
+        //  2: jf [20], #10
+        //  5: out [20]
+        //  7: jt #1, #0
+        // 10: hlt
+        let p = Program::parse("3,20,1006,20,10,4,20,1105,1,0,99").unwrap();
+        let cfg = Cfg::build(&p);
+
+        let starts : Vec<usize> = cfg.blocks.keys().cloned().collect();
+        assert_eq!(starts, vec![0, 5, 10]);
+        assert_eq!(cfg.blocks[&0].successors, vec![10, 5]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Jump);
+        assert_eq!(cfg.blocks[&5].successors, vec![0]);
+        assert_eq!(cfg.blocks[&10].exit, Exit::Halt);
+        assert!(cfg.self_modifying.is_empty());
+    }
+
+    #[test]
+    fn indirect_and_self_modifying() {
+        //  0: add #1, #2, [5]
+        //  4: jt  [0], [9]
+        //  7: hlt
+        let p = Program::parse("1101,1,2,5,5,0,9,99").unwrap();
+        let cfg = Cfg::build(&p);
+
+        assert_eq!(cfg.blocks[&0].exit, Exit::Indirect);
+        assert_eq!(cfg.blocks[&0].successors, vec![7]);
+        assert_eq!(cfg.self_modifying, vec![(0, 5)]);
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b0 -> indirect [style=dashed];"));
+        assert!(dot.contains("b0 -> b0 [style=dotted, color=red, label=\"writes 5\"];"));
+        assert!(dot.contains("b7 [label=\"7: hlt\\l\", peripheries=2];"));
+    }
+
+    #[test]
+    fn puzzle_programs() {
+        for source in &[include_str!("../../inputs/day9.txt"), include_str!("../../inputs/day17.txt")] {
+            let cfg = Cfg::build(&Program::parse(source).unwrap());
+            assert!(cfg.blocks.values().any(|b| b.exit == Exit::Halt));
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use super::{Program,ProgramState,InvalidInstruction};
+use super::limits::{Budget,Limits,RunResult};
 
 pub struct Computer {
     did_abort: bool
@@ -34,4 +35,119 @@ impl Computer {
         Ok(())
     }
 
+    /// Like `run`, but stops early once any of `limits` is reached, and
+    /// reports why it stopped. Since a blocked program makes no progress,
+    /// `io_fn` returning None for input fails with `InputExhausted` rather
+    /// than polling again.
+    pub fn run_limited<IOF>(program: &mut Program, limits: Limits, mut io_fn: IOF) -> Result<RunResult, InvalidInstruction>
+     where IOF: FnMut(IOEvent, &mut Computer) -> Option<isize> {
+        let mut computer   = Computer { did_abort: false };
+        let mut budget     = Budget::new(limits);
+        let mut next_input = None;
+
+        while !computer.did_abort {
+            // Input already taken from io_fn has to be consumed, or it would
+            // be lost; the limits were checked before asking for it.
+            if next_input.is_none() {
+                if let Some(limit) = budget.exceeded() {
+                    return Ok(budget.stop(limit, program.snapshot()));
+                }
+            }
+
+            let state = program.step(next_input)?;
+            budget.record(state, next_input.is_some());
+            next_input = None;
+
+            match state {
+                ProgramState::Halted           => { return Ok(RunResult::Halted) },
+                ProgramState::Blocked          => {
+                    next_input = io_fn(IOEvent::Input, &mut computer);
+                    if next_input.is_none() && !computer.did_abort {
+                        return Err(InvalidInstruction::InputExhausted { ip: program.ip() });
+                    }
+                },
+                ProgramState::Running(None)    => {  },
+                ProgramState::Running(Some(v)) => { io_fn(IOEvent::Output(v), &mut computer); }
+            };
+        };
+
+        Ok(RunResult::Aborted)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Limit,LimitReached};
+    use std::time::Duration;
+
+    // outputs 1, 2, 3, ... forever
+    const COUNTER : &str = "1001,9,1,9,4,9,1105,1,0,0";
+
+    #[test]
+    fn step_limit_resumes() {
+        let mut p = Program::parse(COUNTER).unwrap();
+
+        let (output, result) = p.execute_limited(&[], Limits::new().steps(7)).unwrap();
+        assert_eq!(output, vec![1, 2]);
+        match result {
+            RunResult::Limited(stop) => {
+                assert_eq!((stop.limit, stop.steps, stop.outputs), (Limit::Steps, 7, 2));
+                assert_eq!(Program::restore(&stop.snapshot), p);
+            },
+            other => panic!("unexpected result {:?}", other)
+        };
+
+        let (output, _) = p.execute_limited(&[], Limits::new().outputs(2)).unwrap();
+        assert_eq!(output, vec![3, 4]);
+    }
+
+    #[test]
+    fn run_limited() {
+        let mut p = Program::parse(COUNTER).unwrap();
+        let mut seen = vec![];
+
+        let result = Computer::run_limited(&mut p, Limits::new().outputs(3), |event, _|{
+            if let IOEvent::Output(v) = event { seen.push(v) }
+            None
+        });
+        assert!(matches!(result, Ok(RunResult::Limited(LimitReached { limit: Limit::Outputs, .. }))));
+        assert_eq!(seen, vec![1, 2, 3]);
+
+        let result = Computer::run_limited(&mut p, Limits::new().timeout(Duration::from_millis(0)), |_, _| None);
+        assert!(matches!(result, Ok(RunResult::Limited(LimitReached { limit: Limit::Deadline, steps: 0, .. }))));
+
+        let result = Computer::run_limited(&mut p, Limits::new(), |_, c|{ c.abort(); None });
+        assert_eq!(result, Ok(RunResult::Aborted));
+
+        let mut p = Program::parse("3,0,4,0,99").unwrap();
+        let result = Computer::run_limited(&mut p, Limits::new().steps(100), |event, _| match event {
+            IOEvent::Input => Some(5),
+            IOEvent::Output(v) => { assert_eq!(v, 5); None }
+        });
+        assert_eq!(result, Ok(RunResult::Halted));
+    }
+
+    #[test]
+    fn run_limited_keeps_input() {
+        // the deadline passes while waiting for input
+        let mut p = Program::parse("3,0,4,0,99").unwrap();
+        let result = Computer::run_limited(&mut p, Limits::new().timeout(Duration::from_millis(50)), |event, _| match event {
+            IOEvent::Input => { std::thread::sleep(Duration::from_millis(100)); Some(5) },
+            IOEvent::Output(_) => None
+        });
+        assert!(matches!(result, Ok(RunResult::Limited(LimitReached { limit: Limit::Deadline, inputs: 1, .. }))));
+        assert_eq!(p.get_mem(0), 5);
+    }
+
+    #[test]
+    fn run_limited_without_input() {
+        let mut p = Program::parse("3,0,99").unwrap();
+        let result = Computer::run_limited(&mut p, Limits::new().steps(10), |_, _| None);
+        assert_eq!(result, Err(InvalidInstruction::InputExhausted { ip: 0 }));
+
+        let result = Computer::run_limited(&mut p, Limits::new(), |_, c|{ c.abort(); None });
+        assert_eq!(result, Ok(RunResult::Aborted));
+    }
 }
@@ -0,0 +1,319 @@
+use std::collections::{HashSet,VecDeque};
+use std::io::{self,BufRead,Write};
+use std::str::FromStr;
+
+use super::{Program,ProgramState,InvalidInstruction,OpCode};
+use super::disasm::{Line,decode_at};
+
+// The most memory cells `x`, or instructions `l`, will show at once.
+const MAX_EXAMINE : usize = 4096;
+
+/// Why the debugger handed control back to the caller.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { addr: usize, old: isize, new: isize },
+    NeedInput,
+    Halted
+}
+
+/// Wraps a `Program`, stepping it one instruction at a time and stopping at
+/// breakpoints (instruction pointer values) and watchpoints (memory writes).
+/// Input is taken from a queue filled with `push_input`; output is collected.
+pub struct Debugger {
+    program:     Program,
+    breakpoints: HashSet<usize>,
+    watchpoints: HashSet<usize>,
+    input:       VecDeque<isize>,
+    output:      Vec<isize>
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Debugger {
+        Debugger {
+            program,
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            input:       VecDeque::new(),
+            output:      vec![]
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn output(&self) -> &[isize] {
+        &self.output
+    }
+
+    pub fn push_input(&mut self, value: isize) {
+        self.input.push_back(value);
+    }
+
+    pub fn add_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.insert(ip);
+    }
+
+    pub fn remove_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.remove(&ip);
+    }
+
+    pub fn add_watchpoint(&mut self, addr: usize) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) {
+        self.watchpoints.remove(&addr);
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> Result<StopReason, InvalidInstruction> {
+        let input = match self.program.instruction()?.opcode {
+            OpCode::Input => self.input.pop_front(),
+            _             => None
+        };
+
+        let write = self.program.pending_write(input);
+        let old = write.map(|addr| self.program.get_mem(addr));
+
+        match self.program.step(input)? {
+            ProgramState::Halted           => return Ok(StopReason::Halted),
+            ProgramState::Blocked          => return Ok(StopReason::NeedInput),
+            ProgramState::Running(Some(v)) => self.output.push(v),
+            ProgramState::Running(None)    => {}
+        };
+
+        match (write, old) {
+            (Some(addr), Some(old)) if self.watchpoints.contains(&addr) => {
+                Ok(StopReason::Watchpoint { addr, old, new: self.program.get_mem(addr) })
+            },
+            _ => Ok(StopReason::Stepped)
+        }
+    }
+
+    /// Run until a breakpoint, watchpoint, halt, or the input queue runs dry.
+    pub fn cont(&mut self) -> Result<StopReason, InvalidInstruction> {
+        loop {
+            match self.step()? {
+                StopReason::Stepped => {
+                    if self.breakpoints.contains(&self.program.ip()) {
+                        return Ok(StopReason::Breakpoint(self.program.ip()));
+                    }
+                },
+                reason => return Ok(reason)
+            };
+        }
+    }
+
+    /// Disassemble up to `count` instructions starting at the instruction pointer.
+    pub fn list(&self, count: usize) -> Vec<Line> {
+        let mut addr = self.program.ip();
+        let size = self.program.size();
+
+        (0..count)
+            .map(|_|{
+                let line = decode_at(&self.program, addr, size)
+                    .unwrap_or(Line::Data { addr, value: self.program.get_mem(addr) });
+                addr += line.width();
+                line
+            })
+            .collect()
+    }
+
+    /// A line oriented command interface, reading commands from `input` and
+    /// writing responses to `out` until `quit` or end of input. Pass
+    /// `stdin().lock()` and `stdout()` for interactive use.
+    ///
+    /// Commands: `s [n]` step, `c` continue, `b ip` / `db ip` set or delete a
+    /// breakpoint, `w addr` / `dw addr` set or delete a watchpoint, `i v..`
+    /// queue input, `r` show registers, `x addr [len]` dump memory,
+    /// `l [n]` list instructions, `o` show output, `q` quit.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        write!(out, "> ")?;
+        out.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            let words : Vec<&str> = line.split_whitespace().collect();
+            let command = words.first().cloned().unwrap_or("");
+            let args = words.get(1..).unwrap_or(&[]);
+
+            match self.command(command, args, &mut out) {
+                Ok(true)                      => return Ok(()),
+                Ok(false)                     => {},
+                Err(ReplError::Usage(message)) => writeln!(out, "{}", message)?,
+                Err(ReplError::Io(e))          => return Err(e)
+            };
+
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Run a single repl command, returning whether it was `quit`.
+    fn command<W: Write>(&mut self, command: &str, args: &[&str], out: &mut W) -> Result<bool, ReplError> {
+        match command {
+            "" => {},
+            "s" | "step" => {
+                for _ in 0..arg(args, 0, 1)? {
+                    let reason = self.step();
+                    if reason != Ok(StopReason::Stepped) {
+                        writeln!(out, "{}", describe(&reason))?;
+                        break;
+                    }
+                }
+                self.write_current(out)?;
+            },
+            "c" | "continue" => {
+                let reason = self.cont();
+                writeln!(out, "{}", describe(&reason))?;
+                self.write_current(out)?;
+            },
+            "b" | "break"   => parse_all(args)?.into_iter().for_each(|a| self.add_breakpoint(a)),
+            "db"            => parse_all(args)?.into_iter().for_each(|a| self.remove_breakpoint(a)),
+            "w" | "watch"   => parse_all(args)?.into_iter().for_each(|a| self.add_watchpoint(a)),
+            "dw"            => parse_all(args)?.into_iter().for_each(|a| self.remove_watchpoint(a)),
+            "i" | "input"   => parse_all(args)?.into_iter().for_each(|a| self.push_input(a)),
+            "r" | "regs"    => writeln!(out, "ip={} base={}", self.program.ip(), self.program.base())?,
+            "o" | "output"  => writeln!(out, "{:?}", self.output)?,
+            "x" | "examine" => {
+                let start : usize = arg(args, 0, self.program.ip())?;
+                let len : usize = arg(args, 1, 8)?;
+                if len > MAX_EXAMINE || start.checked_add(len).is_none() {
+                    return Err(ReplError::Usage(format!("can't examine {} cells from {}", len, start)));
+                }
+                writeln!(out, "{:>5}: {:?}", start, self.program.mem_range(start, len))?;
+            },
+            "l" | "list" => {
+                let count : usize = arg(args, 0, 5)?;
+                if count > MAX_EXAMINE {
+                    return Err(ReplError::Usage(format!("can't list {} instructions", count)));
+                }
+                for line in self.list(count) {
+                    writeln!(out, "{:>5}: {}", line.addr(), line)?;
+                }
+            },
+            "q" | "quit" => return Ok(true),
+            _ => writeln!(out, "unknown command: {}", command)?
+        };
+
+        Ok(false)
+    }
+
+    fn write_current<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let line = &self.list(1)[0];
+        writeln!(out, "{:>5}: {}", line.addr(), line)
+    }
+}
+
+// A repl command either failed to write its response, or was given bad
+// arguments, which is reported and otherwise ignored.
+enum ReplError {
+    Io(io::Error),
+    Usage(String)
+}
+
+impl From<io::Error> for ReplError {
+    fn from(e: io::Error) -> ReplError {
+        ReplError::Io(e)
+    }
+}
+
+fn parse_all<T: FromStr>(args: &[&str]) -> Result<Vec<T>, ReplError> {
+    args.iter()
+        .map(|a| a.parse().map_err(|_| ReplError::Usage(format!("invalid argument: {}", a))))
+        .collect()
+}
+
+// The argument at `i`, or `default` if there are fewer arguments.
+fn arg<T: FromStr>(args: &[&str], i: usize, default: T) -> Result<T, ReplError> {
+    match args.get(i) {
+        Some(a) => a.parse().map_err(|_| ReplError::Usage(format!("invalid argument: {}", a))),
+        None    => Ok(default)
+    }
+}
+
+fn describe(reason: &Result<StopReason, InvalidInstruction>) -> String {
+    match reason {
+        Ok(StopReason::Stepped)        => "stepped".to_string(),
+        Ok(StopReason::Breakpoint(ip)) => format!("breakpoint at {}", ip),
+        Ok(StopReason::NeedInput)      => "waiting for input".to_string(),
+        Ok(StopReason::Halted)         => "halted".to_string(),
+        Ok(StopReason::Watchpoint { addr, old, new }) => format!("watchpoint [{}]: {} -> {}", addr, old, new),
+        Err(e) => format!("error: {}", e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // count down from 3, printing each value
+    const COUNTDOWN : &str = "4,10,1001,10,-1,10,1005,10,0,99,3";
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let mut d = Debugger::new(Program::parse(COUNTDOWN).unwrap());
+
+        d.add_breakpoint(6);
+        assert_eq!(d.cont(), Ok(StopReason::Breakpoint(6)));
+        assert_eq!(d.output(), &[3]);
+
+        d.remove_breakpoint(6);
+        d.add_watchpoint(10);
+        assert_eq!(d.cont(), Ok(StopReason::Watchpoint { addr: 10, old: 2, new: 1 }));
+        assert_eq!(d.program().ip(), 6);
+
+        d.remove_watchpoint(10);
+        assert_eq!(d.cont(), Ok(StopReason::Halted));
+        assert_eq!(d.output(), &[3, 2, 1]);
+    }
+
+    #[test]
+    fn input_queue() {
+        let mut d = Debugger::new(Program::parse("3,5,4,5,99,0").unwrap());
+
+        assert_eq!(d.cont(), Ok(StopReason::NeedInput));
+        d.push_input(42);
+        assert_eq!(d.cont(), Ok(StopReason::Halted));
+        assert_eq!(d.output(), &[42]);
+    }
+
+    #[test]
+    fn repl_script() {
+        let mut d = Debugger::new(Program::parse(COUNTDOWN).unwrap());
+        let script = "b 9\nc\nr\nx 10 1\no\nq\ns\n";
+        let mut out = vec![];
+
+        d.repl(Cursor::new(script), &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("breakpoint at 9"));
+        assert!(text.contains("    9: hlt"));
+        assert!(text.contains("ip=9 base=0"));
+        assert!(text.contains("   10: [0]"));
+        assert!(text.contains("[3, 2, 1]"));
+        assert_eq!(d.program().ip(), 9);
+    }
+
+    #[test]
+    fn repl_bad_arguments() {
+        let mut d = Debugger::new(Program::parse(COUNTDOWN).unwrap());
+        let script = "x -1\nx 0 -1\nx 5 18446744073709551615\nb -3\ndw x\nx 10 1\n";
+        let mut out = vec![];
+
+        d.repl(Cursor::new(script), &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("invalid argument: -1"));
+        assert!(text.contains("can't examine 18446744073709551615 cells from 5"));
+        assert!(text.contains("invalid argument: -3"));
+        assert!(text.contains("invalid argument: x"));
+        assert!(text.contains("   10: [3]"));
+    }
+}
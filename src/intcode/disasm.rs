@@ -0,0 +1,155 @@
+use std::fmt;
+
+use super::Program;
+use super::instruction::*;
+
+/// A single operand, as it appears in memory, along with how it is addressed.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Operand {
+    pub mode:  AddressMode,
+    pub value: isize
+}
+
+/// One line of a disassembly listing: either a decoded instruction or
+/// a memory cell that could not be decoded as one.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Line {
+    Code { addr: usize, instr: Instruction, args: Vec<Operand> },
+    Data { addr: usize, value: isize }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.mode {
+            AddressMode::Position  => write!(f, "[{}]", self.value),
+            AddressMode::Immediate => write!(f, "#{}", self.value),
+            AddressMode::Relative  => write!(f, "rb{:+}", self.value)
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Line::Code { instr, args, .. } => {
+                let args : Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                if args.is_empty() {
+                    write!(f, "{}", instr.opcode.mnemonic())
+                } else {
+                    write!(f, "{:<4}{}", instr.opcode.mnemonic(), args.join(", "))
+                }
+            },
+            Line::Data { value, .. } => write!(f, ".data {}", value)
+        }
+    }
+}
+
+impl Line {
+    /// The address of the first memory cell covered by this line.
+    pub fn addr(&self) -> usize {
+        match self {
+            Line::Code { addr, .. } => *addr,
+            Line::Data { addr, .. } => *addr
+        }
+    }
+
+    /// The number of memory cells covered by this line.
+    pub fn width(&self) -> usize {
+        match self {
+            Line::Code { args, .. } => args.len() + 1,
+            Line::Data { .. } => 1
+        }
+    }
+}
+
+/// Decode the instruction at `addr`, if the cell holds a valid opcode whose
+/// operands all fit within `size` and which never writes to an immediate.
+pub fn decode_at(program: &Program, addr: usize, size: usize) -> Option<Line> {
+    let value = program.get_mem(addr);
+    if value < 0 {
+        return None;
+    }
+
+    let instr = Instruction::decode(addr, value as usize).ok()?;
+    let arity = instr.opcode.arity();
+
+    if addr + arity >= size {
+        return None;
+    }
+
+    if let Some(w) = instr.opcode.write_operand() {
+        if instr.modes[w] == AddressMode::Immediate {
+            return None;
+        }
+    }
+
+    let args = (0..arity)
+        .map(|i| Operand { mode: instr.modes[i], value: program.get_mem(addr + i + 1) })
+        .collect();
+
+    Some(Line::Code { addr, instr, args })
+}
+
+/// Walk the program's memory from address 0, decoding each instruction in turn.
+/// Cells that do not decode are emitted as `.data` lines and skipped over one
+/// at a time.
+pub fn disassemble(program: &Program) -> Vec<Line> {
+    let size = program.size();
+    let mut lines = vec![];
+    let mut addr = 0;
+
+    while addr < size {
+        let line = decode_at(program, addr, size)
+            .unwrap_or(Line::Data { addr, value: program.get_mem(addr) });
+
+        addr += line.width();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Render a disassembly as text, one line per instruction, prefixed by address.
+pub fn listing(program: &Program) -> String {
+    disassemble(program)
+        .iter()
+        .map(|line| format!("{:>5}: {}\n", line.addr(), line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_modes() {
+        let p = Program::parse("1002,4,3,4,33").unwrap();
+        let lines = disassemble(&p);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].to_string(), "mul [4], #3, [4]");
+        assert_eq!(lines[1].to_string(), ".data 33");
+    }
+
+    #[test]
+    fn disassemble_relative() {
+        let p = Program::parse("109,-3,21101,1,2,3,204,5,99").unwrap();
+
+        assert_eq!(listing(&p), concat!(
+            "    0: rbo #-3\n",
+            "    2: add #1, #2, rb+3\n",
+            "    6: out rb+5\n",
+            "    8: hlt\n"
+        ));
+    }
+
+    #[test]
+    fn disassemble_data_fallback() {
+        // immediate write target, negative value, truncated instruction, bad opcode
+        let p = Program::parse("11101,-7,1,0").unwrap();
+        let lines = disassemble(&p);
+
+        assert!(lines.iter().all(|l| matches!(l, Line::Data { .. })));
+        assert_eq!(lines.len(), 4);
+    }
+}
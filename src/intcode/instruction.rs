@@ -1,5 +1,4 @@
 use std::error::Error;
-use std::convert::TryFrom;
 use std::fmt;
 
 use num_traits::cast::FromPrimitive;
@@ -11,7 +10,7 @@ pub enum AddressMode {
     Relative  = 2
 }
 
-#[derive(Debug,Clone,Copy,PartialEq,FromPrimitive)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,FromPrimitive)]
 pub enum OpCode {
     Add      = 1,
     Multiply = 2,
@@ -25,17 +24,74 @@ pub enum OpCode {
     Halt     = 99
 }
 
-#[derive(Debug,PartialEq)]
+impl OpCode {
+    /// The assembly mnemonic used for this opcode.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            OpCode::Add      => "add",
+            OpCode::Multiply => "mul",
+            OpCode::Input    => "in",
+            OpCode::Output   => "out",
+            OpCode::JmpTrue  => "jt",
+            OpCode::JmpFalse => "jf",
+            OpCode::LessThan => "lt",
+            OpCode::Equals   => "eq",
+            OpCode::SetBase  => "rbo",
+            OpCode::Halt     => "hlt"
+        }
+    }
+
+    /// Look up an opcode by its assembly mnemonic.
+    pub fn from_mnemonic(name: &str) -> Option<OpCode> {
+        let all = [
+            OpCode::Add, OpCode::Multiply, OpCode::Input, OpCode::Output, OpCode::JmpTrue,
+            OpCode::JmpFalse, OpCode::LessThan, OpCode::Equals, OpCode::SetBase, OpCode::Halt
+        ];
+
+        all.iter().cloned().find(|op| op.mnemonic() == name)
+    }
+
+    /// The number of operands that follow this opcode in memory.
+    pub fn arity(&self) -> usize {
+        match self {
+            OpCode::Add | OpCode::Multiply | OpCode::LessThan | OpCode::Equals => 3,
+            OpCode::JmpTrue | OpCode::JmpFalse => 2,
+            OpCode::Input | OpCode::Output | OpCode::SetBase => 1,
+            OpCode::Halt => 0
+        }
+    }
+
+    /// The index of the operand this opcode writes to, if any.
+    pub fn write_operand(&self) -> Option<usize> {
+        match self {
+            OpCode::Add | OpCode::Multiply | OpCode::LessThan | OpCode::Equals => Some(2),
+            OpCode::Input => Some(0),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub struct Instruction {
     pub opcode: OpCode,
     pub modes:  Vec<AddressMode>
 }
 
-#[derive(Debug,PartialEq)]
+/// Everything that can go wrong decoding or executing an instruction. Faults
+/// carry the instruction pointer and opcode of the faulting instruction, and
+/// the index of the operand involved where there is one. An arithmetic
+/// overflow is reported against the operand the result was to be written to.
+#[derive(Debug,Clone,PartialEq)]
 pub enum InvalidInstruction {
-    InvalidOpcode(usize),
-    InvalidAddressMode(usize),
-    AttemptedImmediateLoad
+    InvalidOpcode { ip: usize, opcode: usize },
+    InvalidAddressMode { ip: usize, opcode: usize, operand: usize, mode: usize },
+    NegativeOpcode { ip: usize, word: isize },
+    AttemptedImmediateLoad { ip: usize, opcode: usize, operand: usize },
+    NegativeAddress { ip: usize, opcode: usize, operand: usize, address: isize },
+    NegativeJump { ip: usize, opcode: usize, target: isize },
+    Overflow { ip: usize, opcode: usize, operand: usize },
+    InputExhausted { ip: usize },
+    CustomFault { ip: usize, opcode: usize, message: String }
 }
 
 // Required for Error trait
@@ -47,12 +103,11 @@ impl fmt::Display for InvalidInstruction {
 
 impl Error for InvalidInstruction {}
 
-impl TryFrom<usize> for Instruction {
-    type Error = InvalidInstruction;
-
-    fn try_from(value: usize) -> Result<Self, Self::Error> {
+impl Instruction {
+    /// Decode the instruction word found at `ip`.
+    pub fn decode(ip: usize, value: usize) -> Result<Instruction, InvalidInstruction> {
         let opcode = OpCode::from_usize(value % 100)
-            .ok_or(InvalidInstruction::InvalidOpcode(value % 100))?;
+            .ok_or(InvalidInstruction::InvalidOpcode { ip, opcode: value % 100 })?;
 
         let mode_values = vec![
             (value / 100)    % 10,
@@ -62,8 +117,10 @@ impl TryFrom<usize> for Instruction {
 
         let modes = mode_values
             .iter()
-            .map(|&v|{
-                AddressMode::from_usize(v).ok_or(InvalidInstruction::InvalidAddressMode(v))
+            .enumerate()
+            .map(|(operand, &mode)|{
+                AddressMode::from_usize(mode)
+                    .ok_or(InvalidInstruction::InvalidAddressMode { ip, opcode: value % 100, operand, mode })
             })
             .collect::<Result<Vec<AddressMode>, InvalidInstruction>>()?;
 
@@ -77,8 +134,8 @@ mod tests {
     use super::*;
 
     #[test]
-    fn try_from_success() {
-        assert_eq!(Instruction::try_from(1002), Ok(Instruction {
+    fn decode_success() {
+        assert_eq!(Instruction::decode(0, 1002), Ok(Instruction {
             opcode: OpCode::Multiply,
             modes: vec![
                 AddressMode::Position,
@@ -89,8 +146,10 @@ mod tests {
     }
 
     #[test]
-    fn try_from_fail() {
-        assert_eq!(Instruction::try_from(5002), Err(InvalidInstruction::InvalidAddressMode(5)));
-        assert_eq!(Instruction::try_from(10032), Err(InvalidInstruction::InvalidOpcode(32)));
+    fn decode_fail() {
+        assert_eq!(Instruction::decode(4, 5002), Err(InvalidInstruction::InvalidAddressMode {
+            ip: 4, opcode: 2, operand: 1, mode: 5
+        }));
+        assert_eq!(Instruction::decode(4, 10032), Err(InvalidInstruction::InvalidOpcode { ip: 4, opcode: 32 }));
     }
 }
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use num_traits::cast::FromPrimitive;
+
+use super::{Program,ProgramState,Memory,OpCode,AddressMode,InvalidInstruction};
+use super::program::execute_with;
+
+type Eval<'a> = Box<dyn FnMut(&[isize]) -> Result<Vec<isize>, String> + 'a>;
+
+/// A user defined instruction.
+pub struct CustomOp<'a> {
+    pub name:   String,
+    pub arity:  usize,
+    pub writes: Vec<usize>,
+    eval:       Eval<'a>
+}
+
+// Operand modes are digits of the instruction word above the opcode, so only
+// as many as fit in an `isize` after the two opcode digits can be given.
+pub const MAX_ARITY : usize = 16;
+
+/// Why a custom instruction couldn't be defined.
+#[derive(Debug,Clone,PartialEq)]
+pub enum DefineError {
+    OpcodeTooLarge(usize),
+    BuiltIn(usize),
+    AlreadyDefined(usize),
+    TooManyOperands(usize),
+    WriteOutOfRange(usize)
+}
+
+impl fmt::Display for DefineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for DefineError {}
+
+/// The built-in instructions plus any number of custom ones.
+///
+/// A custom instruction's evaluation closure receives the values of its
+/// operands that aren't write targets, in order, and returns the values to
+/// store in its write targets, in order. Address modes work just as they do
+/// for built-in instructions. Returning an error stops the program with
+/// `InvalidInstruction::CustomFault`.
+///
+/// Programs using custom instructions must be run with `InstructionSet::step`
+/// or `InstructionSet::execute` rather than the methods on `Program`.
+pub struct InstructionSet<'a> {
+    ops: HashMap<usize, CustomOp<'a>>
+}
+
+impl<'a> InstructionSet<'a> {
+    pub fn new() -> InstructionSet<'a> {
+        InstructionSet { ops: HashMap::new() }
+    }
+
+    /// Add an instruction with the given opcode and number of operands, of
+    /// which those at indices in `writes` are write targets.
+    ///
+    /// Fails if the opcode is built in, already defined or above 99, or if
+    /// there are more than `MAX_ARITY` operands.
+    pub fn define<F>(&mut self, opcode: usize, name: &str, arity: usize, writes: &[usize], eval: F)
+                     -> Result<(), DefineError>
+    where F : FnMut(&[isize]) -> Result<Vec<isize>, String> + 'a {
+        if opcode >= 100 {
+            return Err(DefineError::OpcodeTooLarge(opcode));
+        }
+        if OpCode::from_usize(opcode).is_some() {
+            return Err(DefineError::BuiltIn(opcode));
+        }
+        if self.ops.contains_key(&opcode) {
+            return Err(DefineError::AlreadyDefined(opcode));
+        }
+        if arity > MAX_ARITY {
+            return Err(DefineError::TooManyOperands(arity));
+        }
+        if let Some(&w) = writes.iter().find(|&&w| w >= arity) {
+            return Err(DefineError::WriteOutOfRange(w));
+        }
+
+        self.ops.insert(opcode, CustomOp {
+            name: name.to_string(),
+            arity,
+            writes: writes.to_vec(),
+            eval: Box::new(eval)
+        });
+        Ok(())
+    }
+
+    pub fn get(&self, opcode: usize) -> Option<&CustomOp<'a>> {
+        self.ops.get(&opcode)
+    }
+
+    /// Step a program, evaluating custom instructions here and delegating
+    /// everything else to `Program::step`.
+    pub fn step<M: Memory>(&mut self, program: &mut Program<M>, input: Option<isize>)
+                           -> Result<ProgramState, InvalidInstruction> {
+        let ip = program.ip();
+        let word = program.get_mem(ip);
+        let opcode = (word % 100) as usize;
+
+        let op = match self.ops.get_mut(&opcode) {
+            Some(op) if word >= 0 => op,
+            _ => return program.step(input)
+        };
+
+        let fault = |message: String| InvalidInstruction::CustomFault { ip, opcode, message };
+
+        let mut args = vec![];
+        let mut targets = vec![];
+        for i in 0..op.arity {
+            let raw = program.get_mem(ip + i + 1);
+            let mode = (word as usize / 10_usize.pow(i as u32 + 2)) % 10;
+            let mode = AddressMode::from_usize(mode)
+                .ok_or(InvalidInstruction::InvalidAddressMode { ip, opcode, operand: i, mode })?;
+
+            let addr = match mode {
+                AddressMode::Immediate if op.writes.contains(&i) => {
+                    return Err(InvalidInstruction::AttemptedImmediateLoad { ip, opcode, operand: i })
+                },
+                AddressMode::Immediate => { args.push(raw); continue },
+                AddressMode::Position  => raw,
+                AddressMode::Relative  => program.base()
+                    .checked_add(raw)
+                    .ok_or(InvalidInstruction::Overflow { ip, opcode, operand: i })?
+            };
+            if addr < 0 {
+                return Err(InvalidInstruction::NegativeAddress { ip, opcode, operand: i, address: addr });
+            }
+
+            if op.writes.contains(&i) {
+                targets.push(addr as usize);
+            } else {
+                args.push(program.get_mem(addr as usize));
+            }
+        }
+
+        let values = (op.eval)(&args).map_err(fault)?;
+        if values.len() != targets.len() {
+            return Err(fault(format!("expected {} results, got {}", targets.len(), values.len())));
+        }
+
+        for (addr, value) in targets.into_iter().zip(values) {
+            program.set_mem(addr, value);
+        }
+
+        let state = ProgramState::Running(None);
+        program.complete(ip + op.arity + 1, state);
+        Ok(state)
+    }
+
+    /// Like `Program::execute`, but with the custom instructions available.
+    pub fn execute<M: Memory>(&mut self, program: &mut Program<M>, input: &[isize])
+                              -> Result<Vec<isize>, InvalidInstruction> {
+        execute_with(program, input, |p, next_input| self.step(p, next_input))
+    }
+}
+
+impl<'a> Default for InstructionSet<'a> {
+    fn default() -> InstructionSet<'a> {
+        InstructionSet::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arithmetic<'a>() -> InstructionSet<'a> {
+        let mut isa = InstructionSet::new();
+        isa.define(10, "div", 3, &[2], |args| match args[1] {
+            0 => Err("division by zero".to_string()),
+            d => Ok(vec![args[0] / d])
+        }).unwrap();
+        isa.define(11, "mod", 3, &[2], |args| match args[1] {
+            0 => Err("division by zero".to_string()),
+            d => Ok(vec![args[0] % d])
+        }).unwrap();
+        isa
+    }
+
+    #[test]
+    fn custom_arithmetic() {
+        // in [20]; div [20], #3, [21]; mod [20], #3, [22]; out [21]; out [22]; hlt
+        let source = "3,20,1010,20,3,21,1011,20,3,22,4,21,4,22,99,0,0,0,0,0,0,0,0";
+        let mut isa = arithmetic();
+
+        let mut p = Program::parse(source).unwrap();
+        assert_eq!(isa.execute(&mut p, &[14]), Ok(vec![4, 2]));
+        assert_eq!(p.state(), ProgramState::Halted);
+
+        let mut p = Program::parse(source).unwrap();
+        assert_eq!(isa.execute(&mut p, &[0]), Ok(vec![0, 0]));
+
+        // without the custom instructions the program is invalid
+        let mut p = Program::parse(source).unwrap();
+        assert_eq!(p.execute(&vec![14]), Err(InvalidInstruction::InvalidOpcode { ip: 2, opcode: 10 }));
+    }
+
+    #[test]
+    fn custom_fault() {
+        let mut isa = arithmetic();
+        let mut p = Program::parse("1010,0,0,0,99").unwrap();
+
+        assert_eq!(isa.step(&mut p, None), Err(InvalidInstruction::CustomFault {
+            ip: 0, opcode: 10, message: "division by zero".to_string()
+        }));
+
+        let mut p = Program::parse("210,-5,0,0,99").unwrap();
+        assert_eq!(isa.step(&mut p, None), Err(InvalidInstruction::NegativeAddress {
+            ip: 0, opcode: 10, operand: 0, address: -5
+        }));
+
+        let mut p = Program::parse("11010,0,0,0,99").unwrap();
+        assert_eq!(isa.step(&mut p, None), Err(InvalidInstruction::AttemptedImmediateLoad {
+            ip: 0, opcode: 10, operand: 2
+        }));
+    }
+
+    #[test]
+    fn syscall_hook() {
+        let mut calls = vec![];
+        {
+            let mut isa = InstructionSet::new();
+            isa.define(50, "sys", 2, &[], |args| { calls.push(args.to_vec()); Ok(vec![]) }).unwrap();
+
+            // sys #1, [0]; sys #2, #7; hlt
+            let mut p = Program::parse("150,1,0,1150,2,7,99").unwrap();
+            assert_eq!(isa.execute(&mut p, &[]), Ok(vec![]));
+            assert_eq!(isa.get(50).map(|op| op.name.as_str()), Some("sys"));
+        }
+        assert_eq!(calls, vec![vec![1, 150], vec![2, 7]]);
+    }
+
+    #[test]
+    fn define_errors() {
+        let mut isa = arithmetic();
+        let nop = |_: &[isize]| Ok(vec![]);
+
+        assert_eq!(isa.define(100, "x", 0, &[], nop), Err(DefineError::OpcodeTooLarge(100)));
+        assert_eq!(isa.define(1, "x", 0, &[], nop), Err(DefineError::BuiltIn(1)));
+        assert_eq!(isa.define(10, "x", 0, &[], nop), Err(DefineError::AlreadyDefined(10)));
+        assert_eq!(isa.define(12, "x", MAX_ARITY + 1, &[], nop), Err(DefineError::TooManyOperands(MAX_ARITY + 1)));
+        assert_eq!(isa.define(12, "x", 2, &[2], nop), Err(DefineError::WriteOutOfRange(2)));
+        assert_eq!(isa.define(12, "x", MAX_ARITY, &[], nop), Ok(()));
+    }
+}
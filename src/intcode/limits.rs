@@ -0,0 +1,115 @@
+use std::time::{Duration,Instant};
+
+use super::{ProgramState,Snapshot};
+
+/// Bounds on how long a program may run. Any combination may be set; a run
+/// stops before the next instruction once any of them is reached.
+#[derive(Debug,Clone,Copy,PartialEq,Default)]
+pub struct Limits {
+    pub max_steps:   Option<usize>,
+    pub max_outputs: Option<usize>,
+    pub deadline:    Option<Instant>
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    pub fn steps(mut self, n: usize) -> Limits {
+        self.max_steps = Some(n);
+        self
+    }
+
+    pub fn outputs(mut self, n: usize) -> Limits {
+        self.max_outputs = Some(n);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Instant) -> Limits {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set the deadline relative to now.
+    pub fn timeout(self, timeout: Duration) -> Limits {
+        self.deadline(Instant::now() + timeout)
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Limit {
+    Steps,
+    Outputs,
+    Deadline
+}
+
+/// Details of a run stopped by a limit. The program is left as it was, so
+/// the run can be resumed by calling the same method again, or later from
+/// the snapshot.
+#[derive(Debug,Clone,PartialEq)]
+pub struct LimitReached {
+    pub limit:    Limit,
+    pub steps:    usize,
+    pub outputs:  usize,
+    /// How many input values were consumed before stopping.
+    pub inputs:   usize,
+    pub snapshot: Snapshot
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum RunResult {
+    Halted,
+    Aborted,
+    Limited(LimitReached)
+}
+
+// Tracks a run's progress against its limits.
+pub(super) struct Budget {
+    limits:  Limits,
+    steps:   usize,
+    outputs: usize,
+    inputs:  usize
+}
+
+impl Budget {
+    pub(super) fn new(limits: Limits) -> Budget {
+        Budget { limits, steps: 0, outputs: 0, inputs: 0 }
+    }
+
+    // The limit that has been reached, if any.
+    pub(super) fn exceeded(&self) -> Option<Limit> {
+        if self.limits.max_steps.map(|n| self.steps >= n).unwrap_or(false) {
+            Some(Limit::Steps)
+        } else if self.limits.max_outputs.map(|n| self.outputs >= n).unwrap_or(false) {
+            Some(Limit::Outputs)
+        } else if self.limits.deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+            Some(Limit::Deadline)
+        } else {
+            None
+        }
+    }
+
+    // Account for one step, given whether input was supplied to it.
+    pub(super) fn record(&mut self, state: ProgramState, input: bool) {
+        match state {
+            ProgramState::Blocked => return,
+            ProgramState::Running(Some(_)) => self.outputs += 1,
+            _ => {}
+        };
+        if input {
+            self.inputs += 1;
+        }
+        self.steps += 1;
+    }
+
+    pub(super) fn stop(&self, limit: Limit, snapshot: Snapshot) -> RunResult {
+        RunResult::Limited(LimitReached {
+            limit,
+            steps:   self.steps,
+            outputs: self.outputs,
+            inputs:  self.inputs,
+            snapshot
+        })
+    }
+}
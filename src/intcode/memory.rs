@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+/// Backing storage for a `Program`'s memory. Cells that have never been
+/// written read as 0.
+pub trait Memory: Debug + Clone + PartialEq {
+    fn from_values(values: Vec<isize>) -> Self;
+    fn get(&self, addr: usize) -> isize;
+    fn set(&mut self, addr: usize, value: isize);
+
+    /// One past the highest address that has been loaded or written.
+    fn size(&self) -> usize;
+
+    /// Every cell that has been loaded or written, in address order.
+    fn cells(&self) -> Vec<(usize, isize)>;
+}
+
+/// Addresses below this limit live in a contiguous vector, grown on demand;
+/// anything higher falls back to a sparse map so that a stray write to a huge
+/// address doesn't allocate gigabytes.
+pub const FLAT_LIMIT : usize = 1 << 20;
+
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct FlatMemory {
+    flat:   Vec<isize>,
+    sparse: HashMap<usize, isize>
+}
+
+impl Memory for FlatMemory {
+    fn from_values(values: Vec<isize>) -> FlatMemory {
+        FlatMemory { flat: values, sparse: HashMap::new() }
+    }
+
+    #[inline]
+    fn get(&self, addr: usize) -> isize {
+        match self.flat.get(addr) {
+            Some(&v) => v,
+            None if self.sparse.is_empty() => 0,
+            None => *self.sparse.get(&addr).unwrap_or(&0)
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, addr: usize, value: isize) {
+        if addr < self.flat.len() {
+            self.flat[addr] = value;
+        } else if addr < FLAT_LIMIT {
+            self.flat.resize(addr + 1, 0);
+            self.flat[addr] = value;
+        } else {
+            self.sparse.insert(addr, value);
+        }
+    }
+
+    fn size(&self) -> usize {
+        let sparse = self.sparse.keys().max().map(|&k| k + 1).unwrap_or(0);
+        self.flat.len().max(sparse)
+    }
+
+    fn cells(&self) -> Vec<(usize, isize)> {
+        let mut sparse : Vec<(usize, isize)> = self.sparse.iter().map(|(&k, &v)| (k, v)).collect();
+        sparse.sort();
+
+        self.flat.iter().cloned().enumerate().chain(sparse).collect()
+    }
+}
+
+/// The original hash map backed memory, kept for comparison.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct SparseMemory {
+    mem: HashMap<usize, isize>
+}
+
+impl Memory for SparseMemory {
+    fn from_values(values: Vec<isize>) -> SparseMemory {
+        SparseMemory { mem: values.into_iter().enumerate().collect() }
+    }
+
+    fn get(&self, addr: usize) -> isize {
+        *self.mem.get(&addr).unwrap_or(&0)
+    }
+
+    fn set(&mut self, addr: usize, value: isize) {
+        self.mem.insert(addr, value);
+    }
+
+    fn size(&self) -> usize {
+        self.mem.keys().max().map(|&k| k + 1).unwrap_or(0)
+    }
+
+    fn cells(&self) -> Vec<(usize, isize)> {
+        let mut cells : Vec<(usize, isize)> = self.mem.iter().map(|(&k, &v)| (k, v)).collect();
+        cells.sort();
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Program,ProgramState};
+    use itertools::Itertools;
+    use std::time::{Duration,Instant};
+
+    #[test]
+    fn flat_memory_grows() {
+        let mut m = FlatMemory::from_values(vec![1, 2, 3]);
+
+        assert_eq!(m.get(10), 0);
+        m.set(10, 7);
+        assert_eq!(m.get(10), 7);
+        assert_eq!(m.size(), 11);
+
+        m.set(FLAT_LIMIT * 4, 9);
+        assert_eq!(m.get(FLAT_LIMIT * 4), 9);
+        assert_eq!(m.size(), FLAT_LIMIT * 4 + 1);
+        assert_eq!(m.cells().last(), Some(&(FLAT_LIMIT * 4, 9)));
+    }
+
+    #[test]
+    fn memories_agree() {
+        let source = include_str!("../../inputs/day9.txt");
+        let mut flat : Program<FlatMemory> = Program::parse_with(source).unwrap();
+        let mut sparse : Program<SparseMemory> = Program::parse_with(source).unwrap();
+
+        assert_eq!(flat.execute(&vec![1]), sparse.execute(&vec![1]));
+    }
+
+    fn day2<M: Memory>(source: &str) -> Option<(isize, isize)> {
+        let p : Program<M> = Program::parse_with(source).unwrap();
+
+        // some noun/verb pairs rewrite the program into an endless loop, so
+        // give up on a run after a fixed number of steps
+        (0..100).cartesian_product(0..100).find(|&(noun, verb)|{
+            let mut p = p.clone();
+            p.set_mem(1, noun);
+            p.set_mem(2, verb);
+
+            for _ in 0..1000 {
+                match p.step(None) {
+                    Ok(ProgramState::Running(_)) => {},
+                    Ok(ProgramState::Halted)     => return p.get_mem(0) == 19690720,
+                    _                            => return false
+                };
+            }
+            false
+        })
+    }
+
+    fn day7<M: Memory>(source: &str) -> isize {
+        let p : Program<M> = Program::parse_with(source).unwrap();
+
+        (5..10).permutations(5).map(|phases|{
+            let mut amps : Vec<Program<M>> = phases.iter().map(|_| p.clone()).collect();
+            let mut inputs : Vec<Vec<isize>> = phases.iter().map(|&phase| vec![phase]).collect();
+            let mut signal = 0;
+            inputs[0].push(0);
+
+            let mut halted = false;
+            while !halted {
+                for i in 0..amps.len() {
+                    loop {
+                        match amps[i].step(None).unwrap() {
+                            ProgramState::Running(Some(v)) => {
+                                signal = v;
+                                inputs[(i + 1) % amps.len()].push(v);
+                            },
+                            ProgramState::Running(None) => {},
+                            ProgramState::Blocked => {
+                                if inputs[i].is_empty() { break }
+                                amps[i].step(Some(inputs[i].remove(0))).unwrap();
+                            },
+                            ProgramState::Halted => { halted = true; break }
+                        };
+                    }
+                }
+            }
+            signal
+        }).max().unwrap()
+    }
+
+    fn day9<M: Memory>(source: &str) -> Vec<isize> {
+        let mut p : Program<M> = Program::parse_with(source).unwrap();
+        p.execute(&vec![2]).unwrap()
+    }
+
+    fn time<T: PartialEq + Debug, F: Fn() -> T>(f: F) -> (Duration, T) {
+        let start = Instant::now();
+        let result = f();
+        (start.elapsed(), result)
+    }
+
+    // Compare the flat and hash map memories on the bundled puzzle inputs:
+    // cargo test --release bench_memory -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_memory() {
+        let day2_input = include_str!("../../inputs/day2.txt");
+        let day7_input = include_str!("../../inputs/day7.txt");
+        let day9_input = include_str!("../../inputs/day9.txt");
+
+        let (sparse, a) = time(|| day2::<SparseMemory>(day2_input));
+        let (flat, b)   = time(|| day2::<FlatMemory>(day2_input));
+        assert_eq!(a, b);
+        println!("day 2 noun/verb search: sparse {:>10?}  flat {:>10?}", sparse, flat);
+
+        let (sparse, a) = time(|| day7::<SparseMemory>(day7_input));
+        let (flat, b)   = time(|| day7::<FlatMemory>(day7_input));
+        assert_eq!(a, b);
+        println!("day 7 feedback search:  sparse {:>10?}  flat {:>10?}", sparse, flat);
+
+        let (sparse, a) = time(|| day9::<SparseMemory>(day9_input));
+        let (flat, b)   = time(|| day9::<FlatMemory>(day9_input));
+        assert_eq!(a, b);
+        println!("day 9 BOOST sensor:     sparse {:>10?}  flat {:>10?}", sparse, flat);
+    }
+}
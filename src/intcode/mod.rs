@@ -1,7 +1,37 @@
 mod instruction;
+mod memory;
 mod program;
 mod computer;
+mod disasm;
+mod asm;
+mod debugger;
+mod trace;
+mod snapshot;
+mod runner;
+mod topology;
+mod search;
+mod symbolic;
+mod isa;
+mod cfg;
+mod profile;
+mod limits;
+mod ascii;
 
 pub use program::{Program,ProgramState};
-pub use instruction::InvalidInstruction;
+pub use memory::{Memory,FlatMemory,SparseMemory};
+pub use instruction::{Instruction,OpCode,AddressMode,InvalidInstruction};
 pub use computer::{Computer,IOEvent};
+pub use disasm::{Line,Operand,disassemble,listing};
+pub use asm::{AsmError,AsmErrorKind,assemble,assemble_words};
+pub use debugger::{Debugger,StopReason};
+pub use trace::{MemWrite,TraceRecord,TraceFormat,TraceError,Tracer,step_traced,trace,first_divergence};
+pub use snapshot::{Snapshot,SnapshotFormat,SnapshotError};
+pub use runner::{Machine,Outputs,outputs};
+pub use topology::{Topology,SeedMismatch};
+pub use search::{SEARCH_STEP_LIMIT,search};
+pub use symbolic::{Poly,Analysis,SymbolicError,analyze};
+pub use isa::{MAX_ARITY,CustomOp,DefineError,InstructionSet};
+pub use cfg::{Cfg,Block,Exit};
+pub use profile::{Profile,Profiler,profile};
+pub use limits::{Limits,Limit,LimitReached,RunResult};
+pub use ascii::{AsciiOutput,encode,run_ascii};
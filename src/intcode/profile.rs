@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration,Instant};
+
+use super::{Program,ProgramState,InvalidInstruction,OpCode,AddressMode,Memory};
+use super::program::execute_with;
+
+/// Execution counts gathered by a `Profiler`. Reads count operands loaded
+/// from memory, not instruction fetches; those are covered by `by_ip`.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Profile {
+    pub steps:     usize,
+    pub elapsed:   Duration,
+    pub by_ip:     HashMap<usize, usize>,
+    pub by_opcode: HashMap<OpCode, usize>,
+    pub reads:     HashMap<usize, usize>,
+    pub writes:    HashMap<usize, usize>
+}
+
+// The `n` entries with the highest counts, ties broken by key.
+fn top<K: Copy + Ord>(counts: &HashMap<K, usize>, n: usize) -> Vec<(K, usize)> {
+    let mut entries : Vec<(K, usize)> = counts.iter().map(|(&k, &c)| (k, c)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(n);
+    entries
+}
+
+impl Profile {
+    /// The most executed instruction addresses, busiest first.
+    pub fn hot_ips(&self, n: usize) -> Vec<(usize, usize)> {
+        top(&self.by_ip, n)
+    }
+
+    /// The most read memory addresses, busiest first.
+    pub fn hot_reads(&self, n: usize) -> Vec<(usize, usize)> {
+        top(&self.reads, n)
+    }
+
+    /// The most written memory addresses, busiest first.
+    pub fn hot_writes(&self, n: usize) -> Vec<(usize, usize)> {
+        top(&self.writes, n)
+    }
+
+    /// Opcodes by number of executions, busiest first.
+    pub fn opcodes(&self) -> Vec<(OpCode, usize)> {
+        let mut entries : Vec<(OpCode, usize)> = self.by_opcode.iter().map(|(&op, &c)| (op, c)).collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then((a.0 as usize).cmp(&(b.0 as usize))));
+        entries
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let percent = |n: usize| 100.0 * n as f64 / self.steps.max(1) as f64;
+
+        writeln!(f, "{} steps in {:?}", self.steps, self.elapsed)?;
+
+        writeln!(f, "opcodes:")?;
+        for (op, n) in self.opcodes() {
+            writeln!(f, "  {:<4}{:>10} {:>5.1}%", op.mnemonic(), n, percent(n))?;
+        }
+
+        writeln!(f, "hot instructions:")?;
+        for (ip, n) in self.hot_ips(10) {
+            writeln!(f, "  {:>5}:{:>10} {:>5.1}%", ip, n, percent(n))?;
+        }
+
+        writeln!(f, "hot reads:")?;
+        for (addr, n) in self.hot_reads(10) {
+            writeln!(f, "  {:>5}:{:>10}", addr, n)?;
+        }
+
+        writeln!(f, "hot writes:")?;
+        for (addr, n) in self.hot_writes(10) {
+            writeln!(f, "  {:>5}:{:>10}", addr, n)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Steps a program while counting what it does. Only instructions that
+/// actually execute are counted, so an input instruction that blocks isn't.
+#[derive(Debug,Default)]
+pub struct Profiler {
+    profile: Profile
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn into_profile(self) -> Profile {
+        self.profile
+    }
+
+    pub fn step<M: Memory>(&mut self, program: &mut Program<M>, input: Option<isize>)
+                           -> Result<ProgramState, InvalidInstruction> {
+        let start = Instant::now();
+        let ip = program.ip();
+
+        // work out what will be read and written before the instruction runs
+        let instr = program.instruction();
+        let reads : Vec<usize> = match &instr {
+            Ok(instr) => (0..instr.opcode.arity())
+                .filter(|&i| instr.opcode.write_operand() != Some(i) && instr.modes[i] != AddressMode::Immediate)
+                .filter_map(|i| program.load_address(instr, i).ok())
+                .collect(),
+            Err(_) => vec![]
+        };
+        let write = program.pending_write(input);
+
+        let state = program.step(input)?;
+        self.profile.elapsed += start.elapsed();
+
+        if state == ProgramState::Blocked {
+            return Ok(state);
+        }
+
+        self.profile.steps += 1;
+        *self.profile.by_ip.entry(ip).or_insert(0) += 1;
+        if let Ok(instr) = instr {
+            *self.profile.by_opcode.entry(instr.opcode).or_insert(0) += 1;
+        }
+        for addr in reads {
+            *self.profile.reads.entry(addr).or_insert(0) += 1;
+        }
+        if let Some(addr) = write {
+            *self.profile.writes.entry(addr).or_insert(0) += 1;
+        }
+
+        Ok(state)
+    }
+
+    pub fn execute<M: Memory>(&mut self, program: &mut Program<M>, input: &[isize])
+                              -> Result<Vec<isize>, InvalidInstruction> {
+        execute_with(program, input, |p, next_input| self.step(p, next_input))
+    }
+}
+
+/// Run a program to completion, returning its output along with a profile.
+pub fn profile<M: Memory>(program: &mut Program<M>, input: &[isize])
+                          -> Result<(Vec<isize>, Profile), InvalidInstruction> {
+    let mut profiler = Profiler::new();
+    let output = profiler.execute(program, input)?;
+    Ok((output, profiler.into_profile()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_counts() {
+        // in [9]; add [9], #1, [9]; out [9]; hlt
+        let mut p = Program::parse("3,9,1001,9,1,9,4,9,99,0").unwrap();
+        let (output, profile) = profile(&mut p, &[41]).unwrap();
+
+        assert_eq!(output, vec![42]);
+        assert_eq!(profile.steps, 4);
+        assert_eq!(profile.hot_ips(2), vec![(0, 1), (2, 1)]);
+        assert_eq!(profile.by_opcode[&OpCode::Add], 1);
+        assert_eq!(profile.reads, vec![(9, 2)].into_iter().collect());
+        assert_eq!(profile.writes, vec![(9, 2)].into_iter().collect());
+    }
+
+    #[test]
+    fn profile_loop() {
+        let source = include_str!("../../inputs/day9.txt");
+        let mut p = Program::parse(source).unwrap();
+        let (output, profile) = profile(&mut p, &[1]).unwrap();
+
+        assert_eq!(output, vec![3507134798]);
+        assert_eq!(profile.by_opcode.values().sum::<usize>(), profile.steps);
+        assert_eq!(profile.by_ip.values().sum::<usize>(), profile.steps);
+        assert!(profile.to_string().starts_with(&format!("{} steps in", profile.steps)));
+    }
+}
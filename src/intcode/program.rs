@@ -1,8 +1,9 @@
 use std::num::ParseIntError;
-use std::convert::TryFrom;
-use std::collections::HashMap;
 
 use super::instruction::*;
+use super::memory::{Memory,FlatMemory};
+use super::snapshot::Snapshot;
+use super::limits::{Budget,Limits,RunResult};
 
 #[derive(Debug,Clone,Copy,PartialEq)]
 pub enum ProgramState {
@@ -12,50 +13,155 @@ pub enum ProgramState {
 }
 
 #[derive(Debug,Clone,PartialEq)]
-pub struct Program {
-    mem:  HashMap<usize, isize>,
-    ip:   usize,
-    base: isize
+pub struct Program<M: Memory = FlatMemory> {
+    mem:   M,
+    ip:    usize,
+    base:  isize,
+    state: ProgramState
 }
 
 impl Program {
     pub fn parse(input: &str) -> Result<Program, ParseIntError> {
+        Program::parse_with(input)
+    }
+}
+
+impl<M: Memory> Program<M> {
+    /// Parse a program into an explicitly chosen memory representation.
+    pub fn parse_with(input: &str) -> Result<Program<M>, ParseIntError> {
         let p = input
             .trim()
             .split(",")
-            .enumerate()
-            .map(|(index, part)|{
-                match part.trim().parse::<isize>() {
-                    Ok(n)  => Ok((index, n)),
-                    Err(e) => Err(e)
-                }
-            })
-            .collect::<Result<HashMap<usize, isize>, ParseIntError>>()?;
+            .map(|part| part.trim().parse::<isize>())
+            .collect::<Result<Vec<isize>, ParseIntError>>()?;
 
-        Ok(Program { mem: p, ip: 0, base: 0 })
+        Ok(Program { mem: M::from_values(p), ip: 0, base: 0, state: ProgramState::Running(None) })
     }
 
-    fn get_mem(&self, index: usize) -> isize {
-        *self.mem.get(&index).unwrap_or(&0)
+    #[inline]
+    pub fn get_mem(&self, index: usize) -> isize {
+        self.mem.get(index)
     }
 
+    /// The number of memory cells from address 0 up to the highest address
+    /// that has been loaded or written.
+    pub fn size(&self) -> usize {
+        self.mem.size()
+    }
+
+    /// Read `len` memory cells starting at `start`.
+    pub fn mem_range(&self, start: usize, len: usize) -> Vec<isize> {
+        (start..start + len).map(|i| self.get_mem(i)).collect()
+    }
+
+    #[inline]
     pub fn set_mem(&mut self, index: usize, value: isize) {
-        self.mem.insert(index, value);
+        self.mem.set(index, value);
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// The current relative base.
+    pub fn base(&self) -> isize {
+        self.base
+    }
+
+    /// The state returned by the most recent call to `step`.
+    pub fn state(&self) -> ProgramState {
+        self.state
+    }
+
+    /// Capture the complete machine state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            size:  self.mem.size(),
+            cells: self.mem.cells().into_iter().filter(|&(_, v)| v != 0).collect(),
+            ip:    self.ip,
+            base:  self.base,
+            state: self.state
+        }
+    }
+
+    /// Rebuild a program from a snapshot, ready to resume exactly where it left off.
+    pub fn restore(snapshot: &Snapshot) -> Program<M> {
+        let mut mem = M::from_values(vec![]);
+        for &(addr, value) in &snapshot.cells {
+            mem.set(addr, value);
+        }
+        if snapshot.size > 0 {
+            let last = snapshot.size - 1;
+            mem.set(last, mem.get(last));
+        }
+
+        Program { mem, ip: snapshot.ip, base: snapshot.base, state: snapshot.state }
+    }
+
+    /// Decode the instruction at the current instruction pointer.
+    pub fn instruction(&self) -> Result<Instruction, InvalidInstruction> {
+        let word = self.get_mem(self.ip);
+        if word < 0 {
+            return Err(InvalidInstruction::NegativeOpcode { ip: self.ip, word });
+        }
+        Instruction::decode(self.ip, word as usize)
+    }
+
+    /// The address the next instruction will write to, if it writes at all.
+    /// An input instruction only writes once input is available.
+    pub fn pending_write(&self, input: Option<isize>) -> Option<usize> {
+        let instr = self.instruction().ok()?;
+        let w = instr.opcode.write_operand()?;
+
+        if instr.opcode == OpCode::Input && input.is_none() {
+            return None;
+        }
+
+        self.load_address(&instr, w).ok()
+    }
+
+    /// Resolve each operand of `instr` at the current instruction pointer: the
+    /// value read for input operands, and the target address for the operand
+    /// that is written to. Operands that cannot be resolved are left as-is.
+    pub fn resolve_operands(&self, instr: &Instruction) -> Vec<isize> {
+        (0..instr.opcode.arity())
+            .map(|i|{
+                let resolved = if instr.opcode.write_operand() == Some(i) {
+                    self.load_address(instr, i).map(|a| a as isize)
+                } else {
+                    self.load_argument(instr, i)
+                };
+                resolved.unwrap_or_else(|_| self.get_mem(self.ip + i + 1))
+            })
+            .collect()
+    }
+
+    // Finish an instruction that was evaluated outside of `step`.
+    pub(super) fn complete(&mut self, ip: usize, state: ProgramState) {
+        self.ip = ip;
+        self.state = state;
     }
 
     pub fn step(&mut self, input: Option<isize>) -> Result<ProgramState, InvalidInstruction> {
-        let instr = Instruction::try_from(self.get_mem(self.ip) as usize)?;
+        let state = self.eval(input)?;
+        self.state = state;
+        Ok(state)
+    }
+
+    fn eval(&mut self, input: Option<isize>) -> Result<ProgramState, InvalidInstruction> {
+        let instr = self.instruction()?;
 
         match instr.opcode {
-            OpCode::Add      => self.eval_basic(&instr, |a, b| a + b)?,
-            OpCode::Multiply => self.eval_basic(&instr, |a, b| a * b)?,
+            OpCode::Add      => self.eval_basic(&instr, |a, b| a.checked_add(b))?,
+            OpCode::Multiply => self.eval_basic(&instr, |a, b| a.checked_mul(b))?,
             OpCode::Input    => if !self.eval_input(&instr, &input)? { return Ok(ProgramState::Blocked); },
-            OpCode::Output   => return Ok(ProgramState::Running(Some(self.eval_output(&instr)))),
-            OpCode::JmpTrue  => self.eval_jmp(&instr, |a, b| if a != 0 { Some(b) } else { None }),
-            OpCode::JmpFalse => self.eval_jmp(&instr, |a, b| if a == 0 { Some(b) } else { None }),
-            OpCode::LessThan => self.eval_basic(&instr, |a, b| if a < b { 1 } else { 0 })?,
-            OpCode::Equals   => self.eval_basic(&instr, |a, b| if a == b { 1 } else { 0 })?,
-            OpCode::SetBase  => self.eval_set_base(&instr),
+            OpCode::Output   => return Ok(ProgramState::Running(Some(self.eval_output(&instr)?))),
+            OpCode::JmpTrue  => self.eval_jmp(&instr, |a, b| if a != 0 { Some(b) } else { None })?,
+            OpCode::JmpFalse => self.eval_jmp(&instr, |a, b| if a == 0 { Some(b) } else { None })?,
+            OpCode::LessThan => self.eval_basic(&instr, |a, b| Some(if a < b { 1 } else { 0 }))?,
+            OpCode::Equals   => self.eval_basic(&instr, |a, b| Some(if a == b { 1 } else { 0 }))?,
+            OpCode::SetBase  => self.eval_set_base(&instr)?,
             OpCode::Halt     => return Ok(ProgramState::Halted)
         };
 
@@ -63,54 +169,77 @@ impl Program {
     }
 
     pub fn execute(&mut self, input: &Vec<isize>) -> Result<Vec<isize>, InvalidInstruction> {
+        execute_with(self, input, |p, next_input| p.step(next_input))
+    }
+
+    /// Like `execute`, but stops early once any of `limits` is reached. The
+    /// outputs produced so far are returned either way.
+    pub fn execute_limited(&mut self, input: &[isize], limits: Limits)
+                           -> Result<(Vec<isize>, RunResult), InvalidInstruction> {
         let mut results = vec![];
+        let mut budget = Budget::new(limits);
         let mut inp = input.iter();
         let mut next_input : Option<isize> = None;
+
         loop {
+            if let Some(limit) = budget.exceeded() {
+                return Ok((results, budget.stop(limit, self.snapshot())));
+            }
+
             let rc = self.step(next_input)?;
+            budget.record(rc, next_input.is_some());
             next_input = None;
 
             match rc {
                 ProgramState::Halted => break,
                 ProgramState::Running(Some(v)) => results.push(v),
                 ProgramState::Blocked => {
-                    next_input = Some(*inp.next().expect("Ran out of input!!"));
+                    let v = inp.next().ok_or(InvalidInstruction::InputExhausted { ip: self.ip })?;
+                    next_input = Some(*v);
                 }
                 _ => {}
             };
         }
 
-        Ok(results)
+        Ok((results, RunResult::Halted))
     }
 
     fn eval_basic<F>(&mut self, instr: &Instruction, f: F) -> Result<(), InvalidInstruction>
-    where F : Fn(isize, isize) -> isize {
-        let arg1 = self.load_argument(self.get_mem(self.ip + 1), instr.modes[0]);
-        let arg2 = self.load_argument(self.get_mem(self.ip + 2), instr.modes[1]);
-        let addr = self.load_address(self.get_mem(self.ip + 3),  instr.modes[2])? as usize;
+    where F : Fn(isize, isize) -> Option<isize> {
+        let arg1 = self.load_argument(instr, 0)?;
+        let arg2 = self.load_argument(instr, 1)?;
+        let addr = self.load_address(instr, 2)?;
 
-        let value = f(arg1, arg2);
+        let value = f(arg1, arg2)
+            .ok_or(InvalidInstruction::Overflow { ip: self.ip, opcode: instr.opcode as usize, operand: 2 })?;
         self.set_mem(addr, value);
         self.ip += 4;
 
         Ok(())
     }
 
-    fn eval_jmp<F>(&mut self, instr: &Instruction, f: F)
+    fn eval_jmp<F>(&mut self, instr: &Instruction, f: F) -> Result<(), InvalidInstruction>
     where F : Fn(isize, isize) -> Option<isize> {
-        let arg1 = self.load_argument(self.get_mem(self.ip + 1), instr.modes[0]);
-        let arg2 = self.load_argument(self.get_mem(self.ip + 2), instr.modes[1]);
+        let arg1 = self.load_argument(instr, 0)?;
+        let arg2 = self.load_argument(instr, 1)?;
 
         self.ip = match f(arg1, arg2) {
+            Some(v) if v < 0 => return Err(InvalidInstruction::NegativeJump { ip: self.ip, opcode: instr.opcode as usize, target: v }),
             Some(v) => v as usize,
             None    => self.ip + 3
         };
+
+        Ok(())
     }
 
-    fn eval_set_base(&mut self, instr: &Instruction) {
-        let arg = self.load_argument(self.get_mem(self.ip + 1), instr.modes[0]);
-        self.base += arg;
+    fn eval_set_base(&mut self, instr: &Instruction) -> Result<(), InvalidInstruction> {
+        let arg = self.load_argument(instr, 0)?;
+        self.base = self.base
+            .checked_add(arg)
+            .ok_or(InvalidInstruction::Overflow { ip: self.ip, opcode: instr.opcode as usize, operand: 0 })?;
         self.ip += 2;
+
+        Ok(())
     }
 
     fn eval_input(&mut self, instr: &Instruction, input: &Option<isize>) -> Result<bool, InvalidInstruction> {
@@ -118,33 +247,131 @@ impl Program {
             return Ok(false);
         }
 
-        let arg = self.load_address(self.get_mem(self.ip + 1), instr.modes[0])?;
-        self.set_mem(arg as usize, input.unwrap());
+        let arg = self.load_address(instr, 0)?;
+        self.set_mem(arg, input.unwrap());
         self.ip += 2;
 
         Ok(true)
     }
 
-    fn eval_output(&mut self, instr: &Instruction) -> isize {
-        let arg = self.load_argument(self.get_mem(self.ip + 1), instr.modes[0]);
+    fn eval_output(&mut self, instr: &Instruction) -> Result<isize, InvalidInstruction> {
+        let arg = self.load_argument(instr, 0)?;
         self.ip += 2;
-        return arg;
+        Ok(arg)
     }
 
-    fn load_argument(&self, value: isize, mode: AddressMode) -> isize {
-        match mode {
-            AddressMode::Position  => self.get_mem(value as usize),
-            AddressMode::Immediate => value,
-            AddressMode::Relative  => self.get_mem((self.base + value) as usize)
+    // Read the value of the given operand of the current instruction.
+    fn load_argument(&self, instr: &Instruction, operand: usize) -> Result<isize, InvalidInstruction> {
+        let value = self.get_mem(self.ip + operand + 1);
+
+        match instr.modes[operand] {
+            AddressMode::Immediate => Ok(value),
+            _ => self.load_address(instr, operand).map(|addr| self.get_mem(addr))
         }
     }
 
-    fn load_address(&self, value: isize, mode: AddressMode) -> Result<usize, InvalidInstruction> {
-        match mode {
-            AddressMode::Position  => Ok(value as usize),
-            AddressMode::Relative  => Ok((self.base + value) as usize),
-            AddressMode::Immediate => Err(InvalidInstruction::AttemptedImmediateLoad)
+    // Determine the address referred to by the given operand of the current instruction.
+    pub(super) fn load_address(&self, instr: &Instruction, operand: usize) -> Result<usize, InvalidInstruction> {
+        let value = self.get_mem(self.ip + operand + 1);
+        let addr = match instr.modes[operand] {
+            AddressMode::Position  => value,
+            AddressMode::Relative  => self.base
+                .checked_add(value)
+                .ok_or(InvalidInstruction::Overflow { ip: self.ip, opcode: instr.opcode as usize, operand })?,
+            AddressMode::Immediate => return Err(InvalidInstruction::AttemptedImmediateLoad {
+                ip: self.ip, opcode: instr.opcode as usize, operand
+            })
+        };
+
+        if addr < 0 {
+            return Err(InvalidInstruction::NegativeAddress { ip: self.ip, opcode: instr.opcode as usize, operand, address: addr });
         }
+
+        Ok(addr as usize)
     }
 }
 
+/// Run a program to completion one `step` at a time, feeding it `input`
+/// whenever it blocks and collecting its output. Shared by the variants of
+/// `execute` that step programs in their own way.
+pub(super) fn execute_with<M, E, F>(program: &mut Program<M>, input: &[isize], mut step: F) -> Result<Vec<isize>, E>
+where M: Memory,
+      E: From<InvalidInstruction>,
+      F: FnMut(&mut Program<M>, Option<isize>) -> Result<ProgramState, E> {
+    let mut results = vec![];
+    let mut inp = input.iter();
+    let mut next_input = None;
+
+    loop {
+        let rc = step(program, next_input)?;
+        next_input = None;
+
+        match rc {
+            ProgramState::Halted => break,
+            ProgramState::Running(Some(v)) => results.push(v),
+            ProgramState::Blocked => {
+                let v = inp.next().ok_or(InvalidInstruction::InputExhausted { ip: program.ip })?;
+                next_input = Some(*v);
+            },
+            _ => {}
+        };
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_address() {
+        let mut p = Program::parse("1,-3,0,0,99").unwrap();
+        assert_eq!(p.step(None), Err(InvalidInstruction::NegativeAddress {
+            ip: 0, opcode: 1, operand: 0, address: -3
+        }));
+
+        let mut p = Program::parse("109,-10,22201,1,2,3,99").unwrap();
+        p.step(None).unwrap();
+        assert_eq!(p.step(None), Err(InvalidInstruction::NegativeAddress {
+            ip: 2, opcode: 1, operand: 0, address: -9
+        }));
+    }
+
+    #[test]
+    fn negative_opcode() {
+        // add #-2, #1, [4]
+        let mut p = Program::parse("1101,-2,1,4,99").unwrap();
+        p.step(None).unwrap();
+        assert_eq!(p.step(None), Err(InvalidInstruction::NegativeOpcode { ip: 4, word: -1 }));
+    }
+
+    #[test]
+    fn immediate_write() {
+        let mut p = Program::parse("11101,1,1,5,99").unwrap();
+        assert_eq!(p.step(None), Err(InvalidInstruction::AttemptedImmediateLoad { ip: 0, opcode: 1, operand: 2 }));
+    }
+
+    #[test]
+    fn negative_jump() {
+        let mut p = Program::parse("1105,1,-4").unwrap();
+        assert_eq!(p.step(None), Err(InvalidInstruction::NegativeJump {
+            ip: 0, opcode: 5, target: -4
+        }));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut p = Program::parse(&format!("1101,{},1,0,99", isize::MAX)).unwrap();
+        assert_eq!(p.step(None), Err(InvalidInstruction::Overflow { ip: 0, opcode: 1, operand: 2 }));
+
+        let mut p = Program::parse(&format!("1102,{},2,0,99", isize::MAX)).unwrap();
+        assert_eq!(p.step(None), Err(InvalidInstruction::Overflow { ip: 0, opcode: 2, operand: 2 }));
+    }
+
+    #[test]
+    fn input_exhausted() {
+        let mut p = Program::parse("3,0,3,0,99").unwrap();
+        assert_eq!(p.execute(&vec![1]), Err(InvalidInstruction::InputExhausted { ip: 2 }));
+    }
+}
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc::{channel,Sender,Receiver,SendError,RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use super::{Program,ProgramState,InvalidInstruction};
+
+// How often a machine waiting for input checks whether it has been aborted.
+const POLL_INTERVAL : Duration = Duration::from_millis(1);
+
+/// A program running on its own thread, reading input from one channel and
+/// writing output to another. This is an alternative to `Computer::run` for
+/// callers that would rather send and receive values than write a callback.
+///
+/// The machine stops when the program halts, when it is aborted, when it
+/// needs input and every input sender has been dropped, or when its output
+/// receiver has been dropped.
+pub struct Machine {
+    input:   Option<Sender<isize>>,
+    output:  Option<Receiver<isize>>,
+    aborted: Arc<AtomicBool>,
+    handle:  thread::JoinHandle<Result<Program, InvalidInstruction>>
+}
+
+impl Machine {
+    /// Spawn a machine with its own input and output channels.
+    pub fn spawn(program: Program) -> Machine {
+        let (input_tx, input_rx) = channel();
+        let (output_tx, output_rx) = channel();
+
+        let mut machine = Machine::spawn_with(program, input_rx, output_tx);
+        machine.input = Some(input_tx);
+        machine.output = Some(output_rx);
+        machine
+    }
+
+    /// Spawn a machine on existing channels, e.g. to read input directly from
+    /// another machine's output. `input` and `output` are unavailable on the
+    /// returned machine; use the other ends of the channels instead.
+    pub fn spawn_with(program: Program, input: Receiver<isize>, output: Sender<isize>) -> Machine {
+        let aborted = Arc::new(AtomicBool::new(false));
+        let flag = aborted.clone();
+
+        let handle = thread::spawn(move || Machine::run(program, input, output, flag));
+
+        Machine { input: None, output: None, aborted, handle }
+    }
+
+    fn run(mut program: Program, input: Receiver<isize>, output: Sender<isize>, aborted: Arc<AtomicBool>)
+           -> Result<Program, InvalidInstruction> {
+        let mut next_input = None;
+
+        while !aborted.load(Ordering::Relaxed) {
+            let state = program.step(next_input)?;
+            next_input = None;
+
+            match state {
+                ProgramState::Halted => break,
+                ProgramState::Running(None) => {},
+                ProgramState::Running(Some(v)) => {
+                    if output.send(v).is_err() {
+                        break;
+                    }
+                },
+                ProgramState::Blocked => loop {
+                    match input.recv_timeout(POLL_INTERVAL) {
+                        Ok(v) => { next_input = Some(v); break },
+                        Err(RecvTimeoutError::Timeout) => {
+                            if aborted.load(Ordering::Relaxed) { break }
+                        },
+                        Err(RecvTimeoutError::Disconnected) => {
+                            if aborted.load(Ordering::Relaxed) { break }
+                            return Err(InvalidInstruction::InputExhausted { ip: program.ip() });
+                        }
+                    }
+                }
+            };
+        }
+
+        Ok(program)
+    }
+
+    /// Send a value to the machine's input. Fails once the machine has stopped.
+    pub fn send(&self, value: isize) -> Result<(), SendError<isize>> {
+        self.input.as_ref().expect("machine was spawned without an input channel").send(value)
+    }
+
+    /// Wait for the next output value, or `None` once the machine has stopped.
+    pub fn recv(&self) -> Option<isize> {
+        self.output.as_ref().expect("machine was spawned without an output channel").recv().ok()
+    }
+
+    /// A clone of the machine's input sender, for wiring to other producers.
+    pub fn input(&self) -> Option<Sender<isize>> {
+        self.input.clone()
+    }
+
+    /// Take ownership of the output receiver, e.g. to pass to `spawn_with`.
+    pub fn take_output(&mut self) -> Option<Receiver<isize>> {
+        self.output.take()
+    }
+
+    /// Iterate over output values until the machine stops.
+    pub fn outputs(&self) -> impl Iterator<Item = isize> + '_ {
+        self.output.iter().flat_map(|rx| rx.iter())
+    }
+
+    /// Ask the machine to stop after its current instruction, just like
+    /// `Computer::abort`. The program can still be retrieved with `join`.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    /// Wait for the machine to stop, returning the program in its final state.
+    pub fn join(self) -> Result<Program, InvalidInstruction> {
+        drop(self.input);
+        self.handle.join().expect("machine thread panicked")
+    }
+}
+
+/// An iterator over a program's output values, drawing input from another
+/// iterator as needed. Stops after the program halts or the first error.
+pub struct Outputs<'a, I: Iterator<Item = isize>> {
+    program: &'a mut Program,
+    input:   I,
+    done:    bool
+}
+
+impl<'a, I: Iterator<Item = isize>> Iterator for Outputs<'a, I> {
+    type Item = Result<isize, InvalidInstruction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut next_input = None;
+
+        while !self.done {
+            let state = match self.program.step(next_input) {
+                Ok(state) => state,
+                Err(e)    => { self.done = true; return Some(Err(e)) }
+            };
+            next_input = None;
+
+            match state {
+                ProgramState::Halted           => self.done = true,
+                ProgramState::Running(Some(v)) => return Some(Ok(v)),
+                ProgramState::Running(None)    => {},
+                ProgramState::Blocked          => match self.input.next() {
+                    Some(v) => next_input = Some(v),
+                    None    => {
+                        self.done = true;
+                        return Some(Err(InvalidInstruction::InputExhausted { ip: self.program.ip() }));
+                    }
+                }
+            };
+        }
+
+        None
+    }
+}
+
+/// Run `program` lazily, yielding each output as it is produced.
+pub fn outputs<I: IntoIterator<Item = isize>>(program: &mut Program, input: I) -> Outputs<'_, I::IntoIter> {
+    Outputs { program, input: input.into_iter(), done: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_echo() {
+        let m = Machine::spawn(Program::parse("3,9,1002,9,2,9,4,9,99,0").unwrap());
+
+        m.send(21).unwrap();
+        assert_eq!(m.recv(), Some(42));
+        assert_eq!(m.recv(), None);
+        assert_eq!(m.join().unwrap().state(), ProgramState::Halted);
+    }
+
+    #[test]
+    fn machine_chain() {
+        // each machine adds one to every value it reads
+        let p = Program::parse("3,9,1001,9,1,9,4,9,1105,1,0").unwrap();
+        let (tx, rx) = channel();
+
+        let mut a = Machine::spawn(p.clone());
+        let b = Machine::spawn_with(p, a.take_output().unwrap(), tx);
+
+        for v in 0..3 {
+            a.send(v).unwrap();
+        }
+        assert_eq!(rx.iter().take(3).collect::<Vec<isize>>(), vec![2, 3, 4]);
+
+        // stopping a disconnects b's input
+        a.abort();
+        assert!(a.join().is_ok());
+        assert!(matches!(b.join(), Err(InvalidInstruction::InputExhausted { .. })));
+    }
+
+    #[test]
+    fn machine_abort() {
+        let m = Machine::spawn(Program::parse("3,0,4,0,1105,1,0").unwrap());
+        let input = m.input().unwrap();
+
+        m.send(9).unwrap();
+        assert_eq!(m.recv(), Some(9));
+
+        // stops even though an input sender is still connected
+        m.abort();
+        assert!(m.join().is_ok());
+        assert!(input.send(1).is_err());
+    }
+
+    #[test]
+    fn machine_input_exhausted() {
+        let m = Machine::spawn(Program::parse("3,0,3,0,99").unwrap());
+        m.send(1).unwrap();
+        assert!(matches!(m.join(), Err(InvalidInstruction::InputExhausted { ip: 2 })));
+    }
+
+    #[test]
+    fn output_iterator() {
+        let mut p = Program::parse("3,0,4,0,3,0,4,0,99").unwrap();
+        let values : Vec<isize> = outputs(&mut p, vec![4, 5]).map(|v| v.unwrap()).collect();
+        assert_eq!(values, vec![4, 5]);
+
+        let mut p = Program::parse("3,0,4,0,3,0,4,0,99").unwrap();
+        let mut it = outputs(&mut p, vec![4]);
+        assert_eq!(it.next(), Some(Ok(4)));
+        assert_eq!(it.next(), Some(Err(InvalidInstruction::InputExhausted { ip: 4 })));
+        assert_eq!(it.next(), None);
+    }
+}
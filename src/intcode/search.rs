@@ -0,0 +1,63 @@
+use std::ops::RangeInclusive;
+
+use itertools::Itertools;
+
+use super::{Program,Memory,Limits,RunResult};
+
+/// Runs that take longer than this are abandoned, since some inputs rewrite a
+/// program into an endless loop.
+pub const SEARCH_STEP_LIMIT : usize = 100_000;
+
+/// Sweep every combination of values written to the given addresses, e.g.
+/// `&[(1, 0..=99), (2, 0..=99)]` for a noun and verb, and return the first
+/// combination for which the program halts with `target` at `addr`.
+///
+/// Combinations are tried in order with the last address varying fastest.
+/// Runs that fault, ask for input or exceed `SEARCH_STEP_LIMIT` steps don't
+/// match.
+pub fn search<M: Memory>(program: &Program<M>, inputs: &[(usize, RangeInclusive<isize>)],
+                         addr: usize, target: isize) -> Option<Vec<isize>> {
+    inputs
+        .iter()
+        .map(|(_, range)| range.clone())
+        .multi_cartesian_product()
+        .find(|values|{
+            let mut p = program.clone();
+            for ((cell, _), &value) in inputs.iter().zip(values) {
+                p.set_mem(*cell, value);
+            }
+
+            halts_with(&mut p, addr, target)
+        })
+}
+
+fn halts_with<M: Memory>(p: &mut Program<M>, addr: usize, target: isize) -> bool {
+    match p.execute_limited(&[], Limits::new().steps(SEARCH_STEP_LIMIT)) {
+        Ok((_, RunResult::Halted)) => p.get_mem(addr) == target,
+        _                          => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_inclusive() {
+        // mem[0] = mem[5] * mem[6], only reachable with the end of both ranges
+        let p = Program::parse("2,5,6,0,99,0,0").unwrap();
+        assert_eq!(search(&p, &[(5, 0..=99), (6, 0..=99)], 0, 99 * 99), Some(vec![99, 99]));
+        assert_eq!(search(&p, &[(5, 0..=99), (6, 0..=99)], 0, 100 * 100), None);
+    }
+
+    #[test]
+    fn search_skips_bad_runs() {
+        // loops forever
+        let p = Program::parse("1105,1,0,0").unwrap();
+        assert_eq!(search(&p, &[(3, 0..=1)], 3, 1), None);
+
+        // asks for input
+        let p = Program::parse("3,0,99").unwrap();
+        assert_eq!(search(&p, &[(1, 0..=1)], 0, 0), None);
+    }
+}
@@ -0,0 +1,301 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self,Read,Write,BufReader,BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::ProgramState;
+
+const MAGIC : &[u8; 4] = b"ICS1";
+const TEXT_HEADER : &str = "intcode-snapshot 1";
+
+/// The complete state of a `Program`: memory, instruction pointer, relative
+/// base and the state returned by its last step. Only non-zero memory cells
+/// are stored, along with the memory size so it can be rebuilt exactly.
+///
+/// Capture with `Program::snapshot` and resume with `Program::restore`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Snapshot {
+    pub size:  usize,
+    pub cells: Vec<(usize, isize)>,
+    pub ip:    usize,
+    pub base:  isize,
+    pub state: ProgramState
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SnapshotFormat {
+    Binary,
+    Text
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Format(String)
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> SnapshotError {
+        SnapshotError::Io(e)
+    }
+}
+
+fn format_error(message: &str) -> SnapshotError {
+    SnapshotError::Format(message.to_string())
+}
+
+// Parse field `i` of a text snapshot line.
+fn field<T: FromStr>(parts: &[&str], i: usize, line: &str) -> Result<T, SnapshotError> {
+    parts
+        .get(i)
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(|| format_error(line))
+}
+
+impl Snapshot {
+    /// Write the compact binary format: a magic number followed by little
+    /// endian fields and (address, value) pairs.
+    pub fn write_binary<W: Write>(&self, mut w: W) -> Result<(), SnapshotError> {
+        let (tag, output) = match self.state {
+            ProgramState::Running(None)    => (0_u8, 0),
+            ProgramState::Running(Some(v)) => (1, v),
+            ProgramState::Blocked          => (2, 0),
+            ProgramState::Halted           => (3, 0)
+        };
+
+        w.write_all(MAGIC)?;
+        w.write_all(&(self.ip as u64).to_le_bytes())?;
+        w.write_all(&(self.base as i64).to_le_bytes())?;
+        w.write_all(&[tag])?;
+        w.write_all(&(output as i64).to_le_bytes())?;
+        w.write_all(&(self.size as u64).to_le_bytes())?;
+        w.write_all(&(self.cells.len() as u64).to_le_bytes())?;
+
+        for &(addr, value) in &self.cells {
+            w.write_all(&(addr as u64).to_le_bytes())?;
+            w.write_all(&(value as i64).to_le_bytes())?;
+        }
+
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(mut r: R) -> Result<Snapshot, SnapshotError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(format_error("not a binary snapshot"));
+        }
+
+        let ip = read_u64(&mut r)? as usize;
+        let base = read_u64(&mut r)? as i64 as isize;
+        let mut tag = [0; 1];
+        r.read_exact(&mut tag)?;
+        let output = read_u64(&mut r)? as i64 as isize;
+        let size = read_u64(&mut r)? as usize;
+        let count = read_u64(&mut r)? as usize;
+
+        let state = match tag[0] {
+            0 => ProgramState::Running(None),
+            1 => ProgramState::Running(Some(output)),
+            2 => ProgramState::Blocked,
+            3 => ProgramState::Halted,
+            _ => return Err(format_error("invalid program state"))
+        };
+
+        let cells = (0..count)
+            .map(|_| Ok((read_u64(&mut r)? as usize, read_u64(&mut r)? as i64 as isize)))
+            .collect::<Result<Vec<(usize, isize)>, SnapshotError>>()?;
+
+        Ok(Snapshot { size, cells, ip, base, state })
+    }
+
+    /// Render the human readable format. Memory is written as runs of
+    /// consecutive non-zero cells, one `mem <start> <values>` line per run.
+    pub fn to_text(&self) -> String {
+        let state = match self.state {
+            ProgramState::Running(None)    => "running".to_string(),
+            ProgramState::Running(Some(v)) => format!("output {}", v),
+            ProgramState::Blocked          => "blocked".to_string(),
+            ProgramState::Halted           => "halted".to_string()
+        };
+
+        let mut text = format!(
+            "{}\nip {}\nbase {}\nstate {}\nsize {}\n",
+            TEXT_HEADER, self.ip, self.base, state, self.size
+        );
+
+        let mut run : Vec<String> = vec![];
+        let mut start = 0;
+        for (i, &(addr, value)) in self.cells.iter().enumerate() {
+            if i > 0 && addr != self.cells[i - 1].0 + 1 {
+                text.push_str(&format!("mem {} {}\n", start, run.join(",")));
+                run.clear();
+            }
+            if run.is_empty() {
+                start = addr;
+            }
+            run.push(value.to_string());
+        }
+        if !run.is_empty() {
+            text.push_str(&format!("mem {} {}\n", start, run.join(",")));
+        }
+
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Snapshot, SnapshotError> {
+        let mut lines = text.lines();
+        if lines.next().map(|l| l.trim()) != Some(TEXT_HEADER) {
+            return Err(format_error("not a text snapshot"));
+        }
+
+        let mut snapshot = Snapshot { size: 0, cells: vec![], ip: 0, base: 0, state: ProgramState::Running(None) };
+
+        for line in lines.map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            let parts : Vec<&str> = line.split_whitespace().collect();
+
+            match parts[0] {
+                "ip"   => snapshot.ip = field(&parts, 1, line)?,
+                "base" => snapshot.base = field(&parts, 1, line)?,
+                "size" => snapshot.size = field(&parts, 1, line)?,
+                "state" => snapshot.state = match parts.get(1) {
+                    Some(&"running") => ProgramState::Running(None),
+                    Some(&"output")  => ProgramState::Running(Some(field(&parts, 2, line)?)),
+                    Some(&"blocked") => ProgramState::Blocked,
+                    Some(&"halted")  => ProgramState::Halted,
+                    _ => return Err(format_error(line))
+                },
+                "mem" => {
+                    let start : usize = field(&parts, 1, line)?;
+                    let values = parts
+                        .get(2)
+                        .ok_or_else(|| format_error(line))?
+                        .split(',')
+                        .map(|v| v.parse::<isize>().map_err(|_| format_error(line)))
+                        .collect::<Result<Vec<isize>, SnapshotError>>()?;
+
+                    for (i, v) in values.into_iter().enumerate() {
+                        let addr = start.checked_add(i).ok_or_else(|| format_error(line))?;
+                        snapshot.cells.push((addr, v));
+                    }
+                },
+                _ => return Err(format_error(line))
+            };
+        }
+
+        Ok(snapshot)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: SnapshotFormat) -> Result<(), SnapshotError> {
+        let mut w = BufWriter::new(File::create(path)?);
+
+        match format {
+            SnapshotFormat::Binary => self.write_binary(w),
+            SnapshotFormat::Text   => {
+                w.write_all(self.to_text().as_bytes())?;
+                w.flush()?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Load a snapshot from disk, detecting which format it was saved in.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        let mut bytes = vec![];
+        BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+
+        if bytes.starts_with(MAGIC) {
+            Snapshot::read_binary(&bytes[..])
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| format_error("not a snapshot"))?;
+            Snapshot::from_text(&text)
+        }
+    }
+}
+
+fn read_u64<R: Read>(r: &mut R) -> Result<u64, SnapshotError> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::Program;
+
+    const INPUT : &str = include_str!("../../inputs/day9.txt");
+
+    // Run until the program first asks for input, then snapshot it.
+    fn blocked_program() -> Program {
+        let mut p = Program::parse(INPUT).unwrap();
+        while p.step(None).unwrap() != ProgramState::Blocked {}
+        p
+    }
+
+    #[test]
+    fn restore_resumes() {
+        let p = blocked_program();
+        let snapshot = p.snapshot();
+        assert_eq!(snapshot.state, ProgramState::Blocked);
+
+        let mut restored = Program::restore(&snapshot);
+        assert_eq!(restored, p);
+        assert_eq!(restored.execute(&vec![1]), Ok(vec![3507134798]));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut p = blocked_program();
+        p.set_mem(5_000_000, -12);
+        let snapshot = p.snapshot();
+
+        let mut bytes = vec![];
+        snapshot.write_binary(&mut bytes).unwrap();
+
+        assert_eq!(Snapshot::read_binary(&bytes[..]).unwrap(), snapshot);
+        assert!(Snapshot::read_binary(&b"nope"[..]).is_err());
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut p = Program::parse("104,7,0,0,3,99").unwrap();
+        p.step(None).unwrap();
+        let snapshot = p.snapshot();
+
+        let text = snapshot.to_text();
+        assert_eq!(text, "intcode-snapshot 1\nip 2\nbase 0\nstate output 7\nsize 6\nmem 0 104,7\nmem 4 3,99\n");
+        assert_eq!(Snapshot::from_text(&text).unwrap(), snapshot);
+        assert_eq!(Program::restore(&snapshot), p);
+
+        for bad in &["ip -2", "size -1", "mem -1 1,2", "mem 18446744073709551615 1,2", "ip"] {
+            let text = format!("{}\n{}\n", TEXT_HEADER, bad);
+            assert!(matches!(Snapshot::from_text(&text), Err(SnapshotError::Format(_))), "{} was accepted", bad);
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let snapshot = blocked_program().snapshot();
+        let dir = std::env::temp_dir();
+
+        for &(ext, format) in &[("bin", SnapshotFormat::Binary), ("txt", SnapshotFormat::Text)] {
+            let path = dir.join(format!("aoc-snapshot-save_and_load-{}.{}", std::process::id(), ext));
+            snapshot.save(&path, format).unwrap();
+            let loaded = Snapshot::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), snapshot);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap,BTreeSet,HashMap};
+use std::error::Error;
+use std::fmt;
+
+use super::{Program,Memory,Instruction,InvalidInstruction,OpCode,AddressMode};
+
+/// A polynomial with integer coefficients over unknown memory cells. Each
+/// monomial is the sorted list of cells multiplied together, so `[1, 1, 2]`
+/// is `[1]*[1]*[2]` and `[]` is the constant term.
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Poly {
+    terms: BTreeMap<Vec<usize>, isize>
+}
+
+impl Poly {
+    pub fn constant(value: isize) -> Poly {
+        let mut p = Poly::default();
+        p.terms.insert(vec![], value);
+        p.terms.retain(|_, &mut c| c != 0);
+        p
+    }
+
+    /// The unknown value of a memory cell.
+    pub fn cell(addr: usize) -> Poly {
+        let mut p = Poly::default();
+        p.terms.insert(vec![addr], 1);
+        p
+    }
+
+    pub fn as_constant(&self) -> Option<isize> {
+        match self.terms.len() {
+            0 => Some(0),
+            1 => self.terms.get(&vec![]).cloned(),
+            _ => None
+        }
+    }
+
+    pub fn coefficient(&self, monomial: &[usize]) -> isize {
+        let mut key = monomial.to_vec();
+        key.sort();
+        *self.terms.get(&key).unwrap_or(&0)
+    }
+
+    /// The unknown cells this polynomial depends on.
+    pub fn cells(&self) -> BTreeSet<usize> {
+        self.terms.keys().flatten().cloned().collect()
+    }
+
+    pub fn degree(&self) -> usize {
+        self.terms.keys().map(|m| m.len()).max().unwrap_or(0)
+    }
+
+    // Add `coefficient * monomial`, dropping the term if it cancels out.
+    fn add_term(&mut self, monomial: Vec<usize>, coefficient: isize) -> Option<()> {
+        let c = self.terms.get(&monomial).unwrap_or(&0).checked_add(coefficient)?;
+        if c == 0 {
+            self.terms.remove(&monomial);
+        } else {
+            self.terms.insert(monomial, c);
+        }
+        Some(())
+    }
+
+    /// Returns `None` if a coefficient overflows.
+    pub fn checked_add(&self, other: &Poly) -> Option<Poly> {
+        let mut p = self.clone();
+        for (m, &c) in &other.terms {
+            p.add_term(m.clone(), c)?;
+        }
+        Some(p)
+    }
+
+    /// Returns `None` if a coefficient overflows.
+    pub fn checked_mul(&self, other: &Poly) -> Option<Poly> {
+        let mut p = Poly::default();
+        for (m1, &c1) in &self.terms {
+            for (m2, &c2) in &other.terms {
+                let mut m : Vec<usize> = m1.iter().chain(m2).cloned().collect();
+                m.sort();
+                p.add_term(m, c1.checked_mul(c2)?)?;
+            }
+        }
+        Some(p)
+    }
+
+    /// Replace an unknown cell with a known value.
+    pub fn substitute(&self, addr: usize, value: isize) -> Option<Poly> {
+        let mut p = Poly::default();
+        for (m, &c) in &self.terms {
+            let mut c = c;
+            for _ in m.iter().filter(|&&a| a == addr) {
+                c = c.checked_mul(value)?;
+            }
+            p.add_term(m.iter().cloned().filter(|&a| a != addr).collect(), c)?;
+        }
+        Some(p)
+    }
+
+    /// Split into `a * [addr] + b`, where neither `a` nor `b` depend on
+    /// `addr`. Returns `None` if `addr` appears with a higher power.
+    pub fn linear_in(&self, addr: usize) -> Option<(Poly, Poly)> {
+        let mut a = Poly::default();
+        let mut b = Poly::default();
+
+        for (m, &c) in &self.terms {
+            match m.iter().filter(|&&x| x == addr).count() {
+                0 => b.add_term(m.clone(), c)?,
+                1 => a.add_term(m.iter().cloned().filter(|&x| x != addr).collect(), c)?,
+                _ => return None
+            };
+        }
+        Some((a, b))
+    }
+}
+
+impl fmt::Display for Poly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+
+        let terms : Vec<String> = self.terms
+            .iter()
+            .map(|(m, &c)|{
+                let cells : Vec<String> = m.iter().map(|a| format!("[{}]", a)).collect();
+                match (c, cells.is_empty()) {
+                    (_, true)  => c.to_string(),
+                    (1, false) => cells.join("*"),
+                    _          => format!("{}*{}", c, cells.join("*"))
+                }
+            })
+            .collect();
+
+        write!(f, "{}", terms.join(" + "))
+    }
+}
+
+/// Reasons a program can't be analyzed symbolically. Only straight line code
+/// made of additions and multiplications is supported.
+#[derive(Debug,Clone,PartialEq)]
+pub enum SymbolicError {
+    Unsupported { ip: usize, opcode: OpCode },
+    UnknownOpcode { ip: usize },
+    UnknownAddress { ip: usize, operand: usize },
+    Overflow { ip: usize },
+    Invalid(InvalidInstruction)
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SymbolicError {}
+
+impl From<InvalidInstruction> for SymbolicError {
+    fn from(e: InvalidInstruction) -> SymbolicError {
+        SymbolicError::Invalid(e)
+    }
+}
+
+/// The memory of a program after running it symbolically. A cell is `None`
+/// if its value was loaded through an address that depends on an unknown,
+/// and so can't be written as a polynomial.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Analysis {
+    cells: HashMap<usize, Option<Poly>>
+}
+
+impl Analysis {
+    /// The final value of a cell in terms of the unknowns.
+    pub fn cell(&self, addr: usize) -> Option<Poly> {
+        self.cells.get(&addr).cloned().unwrap_or_else(|| Some(Poly::default()))
+    }
+
+    /// The unknown cells the final value of `addr` depends on.
+    pub fn depends_on(&self, addr: usize) -> Option<BTreeSet<usize>> {
+        self.cell(addr).map(|p| p.cells())
+    }
+}
+
+struct State {
+    cells: HashMap<usize, Option<Poly>>,
+    ip:    usize,
+    base:  isize
+}
+
+impl State {
+    fn get(&self, addr: usize) -> Option<Poly> {
+        self.cells.get(&addr).cloned().unwrap_or_else(|| Some(Poly::default()))
+    }
+
+    fn address(&self, instr: &Instruction, operand: usize) -> Result<Option<usize>, SymbolicError> {
+        let raw = match self.get(self.ip + 1 + operand).and_then(|p| p.as_constant()) {
+            Some(raw) => raw,
+            None      => return Ok(None)
+        };
+
+        let address = match instr.modes[operand] {
+            AddressMode::Relative => self.base.checked_add(raw).ok_or(SymbolicError::Overflow { ip: self.ip })?,
+            _                     => raw
+        };
+
+        if address < 0 {
+            return Err(InvalidInstruction::NegativeAddress {
+                ip: self.ip, opcode: instr.opcode as usize, operand, address
+            }.into());
+        }
+        Ok(Some(address as usize))
+    }
+
+    fn argument(&self, instr: &Instruction, operand: usize) -> Result<Option<Poly>, SymbolicError> {
+        match instr.modes[operand] {
+            AddressMode::Immediate => Ok(self.get(self.ip + 1 + operand)),
+            _ => Ok(self.address(instr, operand)?.and_then(|a| self.get(a)))
+        }
+    }
+}
+
+/// Run `program` from its current state with the cells at `unknowns` treated
+/// as variables, until it halts. The program may only add and multiply, and
+/// every opcode and write address it uses must not depend on an unknown.
+pub fn analyze<M: Memory>(program: &Program<M>, unknowns: &[usize]) -> Result<Analysis, SymbolicError> {
+    let snapshot = program.snapshot();
+    let mut state = State {
+        cells: snapshot.cells.iter().map(|&(a, v)| (a, Some(Poly::constant(v)))).collect(),
+        ip:    snapshot.ip,
+        base:  snapshot.base
+    };
+    for &addr in unknowns {
+        state.cells.insert(addr, Some(Poly::cell(addr)));
+    }
+
+    loop {
+        let ip = state.ip;
+        let word = state.get(ip)
+            .and_then(|p| p.as_constant())
+            .ok_or(SymbolicError::UnknownOpcode { ip })?;
+        if word < 0 {
+            return Err(InvalidInstruction::NegativeOpcode { ip, word }.into());
+        }
+
+        let instr = Instruction::decode(ip, word as usize)?;
+        let f : fn(&Poly, &Poly) -> Option<Poly> = match instr.opcode {
+            OpCode::Halt     => break,
+            OpCode::Add      => Poly::checked_add,
+            OpCode::Multiply => Poly::checked_mul,
+            opcode           => return Err(SymbolicError::Unsupported { ip, opcode })
+        };
+
+        if instr.modes[2] == AddressMode::Immediate {
+            return Err(InvalidInstruction::AttemptedImmediateLoad { ip, opcode: instr.opcode as usize, operand: 2 }.into());
+        }
+
+        let result = match (state.argument(&instr, 0)?, state.argument(&instr, 1)?) {
+            (Some(a), Some(b)) => Some(f(&a, &b).ok_or(SymbolicError::Overflow { ip })?),
+            _                  => None
+        };
+        let target = state.address(&instr, 2)?
+            .ok_or(SymbolicError::UnknownAddress { ip, operand: 2 })?;
+
+        state.cells.insert(target, result);
+        state.ip += 4;
+    }
+
+    Ok(Analysis { cells: state.cells })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poly_arithmetic() {
+        let x = Poly::cell(1);
+        let y = Poly::cell(2);
+
+        let p = x.checked_add(&Poly::constant(3)).unwrap().checked_mul(&y).unwrap();
+        assert_eq!(p.to_string(), "[1]*[2] + 3*[2]");
+        assert_eq!(p.degree(), 2);
+        assert_eq!(p.substitute(1, 2).unwrap().to_string(), "5*[2]");
+        assert_eq!(p.substitute(1, -3).unwrap(), Poly::default());
+
+        let (a, b) = p.linear_in(1).unwrap();
+        assert_eq!((a, b), (y.clone(), Poly::constant(3).checked_mul(&y).unwrap()));
+        assert_eq!(p.coefficient(&[2, 1]), 1);
+        assert_eq!(p.checked_mul(&x).unwrap().linear_in(1), None);
+    }
+
+    #[test]
+    fn analyze_dependencies() {
+        // mem[11] = [9] * [10]; mem[0] = mem[11] + [9]
+        let p = Program::parse("2,9,10,11,1,11,9,0,99,0,0,0").unwrap();
+        let analysis = analyze(&p, &[9, 10]).unwrap();
+
+        assert_eq!(analysis.cell(0).unwrap().to_string(), "[9] + [9]*[10]");
+        assert_eq!(analysis.depends_on(0), Some(vec![9, 10].into_iter().collect()));
+        assert_eq!(analysis.depends_on(4), Some(BTreeSet::new()));
+
+        // the first instruction now loads through an unknown address
+        let analysis = analyze(&p, &[1]).unwrap();
+        assert_eq!(analysis.cell(0), None);
+        assert_eq!(analysis.depends_on(11), None);
+    }
+
+    #[test]
+    fn analyze_unsupported() {
+        let p = Program::parse("1,0,0,0,3,0,99").unwrap();
+        assert_eq!(analyze(&p, &[]), Err(SymbolicError::Unsupported { ip: 4, opcode: OpCode::Input }));
+
+        // the second instruction's opcode depends on [2]
+        let p = Program::parse("1,2,0,4,0,0,0,0,99").unwrap();
+        assert_eq!(analyze(&p, &[2]), Err(SymbolicError::UnknownOpcode { ip: 4 }));
+
+        let p = Program::parse("1,0,0,0,-7").unwrap();
+        assert_eq!(analyze(&p, &[]), Err(InvalidInstruction::NegativeOpcode { ip: 4, word: -7 }.into()));
+
+        // set the relative base, then add [rb+1] to itself
+        let mut p = Program::parse(&format!("109,{},22201,1,1,0,99", isize::MAX)).unwrap();
+        p.step(None).unwrap();
+        assert_eq!(analyze(&p, &[]), Err(SymbolicError::Overflow { ip: 2 }));
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+use super::{Program,ProgramState,InvalidInstruction};
+
+struct Node {
+    program: Program,
+    queue:   VecDeque<isize>,
+    outputs: Vec<isize>,
+    targets: Vec<usize>
+}
+
+/// A chain or ring was given a different number of seeds than programs.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SeedMismatch {
+    pub programs: usize,
+    pub seeds:    usize
+}
+
+impl fmt::Display for SeedMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for SeedMismatch {}
+
+/// A set of machines whose outputs feed each other's inputs. Machines are
+/// stepped round-robin on the calling thread, so runs are deterministic.
+///
+/// Each node's output goes to every node it is connected to, and is also
+/// recorded so it can be inspected once the network has settled.
+pub struct Topology {
+    nodes: Vec<Node>
+}
+
+impl Topology {
+    pub fn new() -> Topology {
+        Topology { nodes: vec![] }
+    }
+
+    /// Nodes connected one after another: 0 -> 1 -> .. -> n-1. There must
+    /// be one seed per program.
+    pub fn chain(programs: Vec<Program>, seeds: Vec<Vec<isize>>) -> Result<Topology, SeedMismatch> {
+        let mut t = Topology::with_nodes(programs, seeds)?;
+        for i in 1..t.len() {
+            t.connect(i - 1, i);
+        }
+        Ok(t)
+    }
+
+    /// A chain whose last node feeds back into the first.
+    pub fn ring(programs: Vec<Program>, seeds: Vec<Vec<isize>>) -> Result<Topology, SeedMismatch> {
+        let mut t = Topology::chain(programs, seeds)?;
+        if !t.is_empty() {
+            t.connect(t.len() - 1, 0);
+        }
+        Ok(t)
+    }
+
+    fn with_nodes(programs: Vec<Program>, seeds: Vec<Vec<isize>>) -> Result<Topology, SeedMismatch> {
+        if programs.len() != seeds.len() {
+            return Err(SeedMismatch { programs: programs.len(), seeds: seeds.len() });
+        }
+
+        let mut t = Topology::new();
+        for (program, seed) in programs.into_iter().zip(seeds) {
+            t.add_node(program, seed);
+        }
+        Ok(t)
+    }
+
+    /// Add a machine whose first inputs are `seed`, returning its index.
+    pub fn add_node(&mut self, program: Program, seed: Vec<isize>) -> usize {
+        self.nodes.push(Node { program, queue: seed.into(), outputs: vec![], targets: vec![] });
+        self.nodes.len() - 1
+    }
+
+    /// Send every output of `from` to the input of `to`.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.nodes[from].targets.push(to);
+    }
+
+    /// Queue an extra input value for a node.
+    pub fn push_input(&mut self, node: usize, value: isize) {
+        self.nodes[node].queue.push_back(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn program(&self, node: usize) -> &Program {
+        &self.nodes[node].program
+    }
+
+    /// Everything a node has output so far.
+    pub fn outputs(&self, node: usize) -> &[isize] {
+        &self.nodes[node].outputs
+    }
+
+    /// True once every machine has halted, as opposed to being stuck waiting
+    /// for input that will never arrive.
+    pub fn halted(&self) -> bool {
+        self.nodes.iter().all(|n| n.program.state() == ProgramState::Halted)
+    }
+
+    /// Run until no machine can make progress, returning each node's outputs.
+    pub fn run(&mut self) -> Result<Vec<Vec<isize>>, InvalidInstruction> {
+        loop {
+            let mut progress = false;
+            for i in 0..self.nodes.len() {
+                progress |= self.run_node(i)?;
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        Ok(self.nodes.iter().map(|n| n.outputs.clone()).collect())
+    }
+
+    // Run a node until it halts or needs input that isn't queued yet.
+    // Returns whether it executed anything.
+    fn run_node(&mut self, i: usize) -> Result<bool, InvalidInstruction> {
+        let mut progress = false;
+        let mut next_input = None;
+
+        loop {
+            let state = self.nodes[i].program.step(next_input)?;
+            next_input = None;
+
+            match state {
+                ProgramState::Halted => return Ok(progress),
+                ProgramState::Running(None) => {},
+                ProgramState::Running(Some(v)) => {
+                    self.nodes[i].outputs.push(v);
+                    for t in self.nodes[i].targets.clone() {
+                        self.nodes[t].queue.push_back(v);
+                    }
+                },
+                ProgramState::Blocked => match self.nodes[i].queue.pop_front() {
+                    Some(v) => next_input = Some(v),
+                    None    => return Ok(progress)
+                }
+            };
+            progress = true;
+        }
+    }
+}
+
+impl Default for Topology {
+    fn default() -> Topology {
+        Topology::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reads a value, adds one and outputs it, forever
+    const INCREMENT : &str = "3,9,1001,9,1,9,4,9,1105,1,0";
+
+    #[test]
+    fn chain_settles() {
+        let p = Program::parse(INCREMENT).unwrap();
+        let mut t = Topology::chain(vec![p.clone(), p.clone(), p.clone()], vec![vec![1, 10], vec![], vec![]]).unwrap();
+
+        let outputs = t.run().unwrap();
+        assert_eq!(outputs, vec![vec![2, 11], vec![3, 12], vec![4, 13]]);
+        assert!(!t.halted());
+
+        assert_eq!(Topology::ring(vec![p], vec![]).err(), Some(SeedMismatch { programs: 1, seeds: 0 }));
+    }
+
+    #[test]
+    fn graph_fan_out() {
+        let p = Program::parse(INCREMENT).unwrap();
+        let halt = Program::parse("3,0,4,0,99").unwrap();
+
+        let mut t = Topology::new();
+        let a = t.add_node(p.clone(), vec![0]);
+        let b = t.add_node(p, vec![]);
+        let c = t.add_node(halt, vec![]);
+        t.connect(a, b);
+        t.connect(a, c);
+
+        t.run().unwrap();
+        assert_eq!(t.outputs(b), &[2]);
+        assert_eq!(t.outputs(c), &[1]);
+        assert_eq!(t.program(c).state(), ProgramState::Halted);
+    }
+}
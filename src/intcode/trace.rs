@@ -0,0 +1,269 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self,Write};
+
+use super::{Program,ProgramState,InvalidInstruction,OpCode};
+use super::disasm::Operand;
+use super::program::execute_with;
+
+/// A memory write performed by a single instruction.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct MemWrite {
+    pub addr: usize,
+    pub old:  isize,
+    pub new:  isize
+}
+
+/// Everything observable about one executed instruction.
+#[derive(Debug,Clone,PartialEq)]
+pub struct TraceRecord {
+    pub step:   usize,
+    pub ip:     usize,
+    pub opcode: OpCode,
+    pub args:   Vec<Operand>,
+    pub values: Vec<isize>,
+    pub write:  Option<MemWrite>,
+    pub base:   Option<(isize, isize)>,
+    pub input:  Option<isize>,
+    pub output: Option<isize>
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum TraceFormat {
+    JsonLines,
+    Text
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Program(InvalidInstruction),
+    Io(io::Error)
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for TraceError {}
+
+impl From<InvalidInstruction> for TraceError {
+    fn from(e: InvalidInstruction) -> TraceError {
+        TraceError::Program(e)
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> TraceError {
+        TraceError::Io(e)
+    }
+}
+
+impl TraceRecord {
+    /// Render as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let list = |v: &[isize]| v.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(",");
+        let option = |v: Option<isize>| v.map(|n| n.to_string()).unwrap_or_else(|| "null".to_string());
+
+        let modes : Vec<isize> = self.args.iter().map(|a| a.mode as isize).collect();
+        let args : Vec<isize> = self.args.iter().map(|a| a.value).collect();
+        let write = match self.write {
+            Some(w) => format!("{{\"addr\":{},\"old\":{},\"new\":{}}}", w.addr, w.old, w.new),
+            None    => "null".to_string()
+        };
+        let base = match self.base {
+            Some((old, new)) => format!("{{\"old\":{},\"new\":{}}}", old, new),
+            None             => "null".to_string()
+        };
+
+        format!(
+            "{{\"step\":{},\"ip\":{},\"opcode\":\"{}\",\"modes\":[{}],\"args\":[{}],\"values\":[{}],\"write\":{},\"base\":{},\"input\":{},\"output\":{}}}",
+            self.step, self.ip, self.opcode.mnemonic(), list(&modes), list(&args), list(&self.values),
+            write, base, option(self.input), option(self.output)
+        )
+    }
+}
+
+// Compact text form, e.g. `12 @ 4: add [9], #3, [9] = 7, 3, 9 | [9] 7 -> 10`
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let args : Vec<String> = self.args.iter().map(|a| a.to_string()).collect();
+        let values : Vec<String> = self.values.iter().map(|v| v.to_string()).collect();
+
+        write!(f, "{} @ {}: {}", self.step, self.ip, self.opcode.mnemonic())?;
+        if !args.is_empty() {
+            write!(f, " {} = {}", args.join(", "), values.join(", "))?;
+        }
+        if let Some(w) = self.write {
+            write!(f, " | [{}] {} -> {}", w.addr, w.old, w.new)?;
+        }
+        if let Some((old, new)) = self.base {
+            write!(f, " | rb {} -> {}", old, new)?;
+        }
+        if let Some(v) = self.input {
+            write!(f, " | in {}", v)?;
+        }
+        if let Some(v) = self.output {
+            write!(f, " | out {}", v)?;
+        }
+        Ok(())
+    }
+}
+
+/// Step `program` once, returning the resulting state along with a record of
+/// what the instruction did. No record is produced when the program blocks
+/// waiting for input, since nothing was executed.
+pub fn step_traced(program: &mut Program, input: Option<isize>, step: usize)
+                   -> Result<(ProgramState, Option<TraceRecord>), InvalidInstruction> {
+    let ip = program.ip();
+    let instr = program.instruction()?;
+    let arity = instr.opcode.arity();
+    let args = program
+        .mem_range(ip + 1, arity)
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| Operand { mode: instr.modes[i], value })
+        .collect();
+    let values = program.resolve_operands(&instr);
+    let write = program.pending_write(input).map(|addr| (addr, program.get_mem(addr)));
+    let base = program.base();
+
+    let state = program.step(input)?;
+    if state == ProgramState::Blocked {
+        return Ok((state, None));
+    }
+
+    let record = TraceRecord {
+        step,
+        ip,
+        opcode: instr.opcode,
+        args,
+        values,
+        write:  write.map(|(addr, old)| MemWrite { addr, old, new: program.get_mem(addr) }),
+        base:   if program.base() != base { Some((base, program.base())) } else { None },
+        input:  if instr.opcode == OpCode::Input { input } else { None },
+        output: match state { ProgramState::Running(v) => v, _ => None }
+    };
+
+    Ok((state, Some(record)))
+}
+
+/// Runs a program to completion, writing one trace record per executed
+/// instruction to `out`.
+pub struct Tracer<W: Write> {
+    out:    W,
+    format: TraceFormat,
+    steps:  usize
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, format: TraceFormat) -> Tracer<W> {
+        Tracer { out, format, steps: 0 }
+    }
+
+    /// The number of instructions traced so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Step the program once, emitting a record if an instruction executed.
+    pub fn step(&mut self, program: &mut Program, input: Option<isize>) -> Result<ProgramState, TraceError> {
+        let (state, record) = step_traced(program, input, self.steps)?;
+
+        if let Some(record) = record {
+            match self.format {
+                TraceFormat::JsonLines => writeln!(self.out, "{}", record.to_json())?,
+                TraceFormat::Text      => writeln!(self.out, "{}", record)?
+            };
+            self.steps += 1;
+        }
+
+        Ok(state)
+    }
+
+    /// Like `Program::execute`, tracing every instruction along the way.
+    /// Fails with `InputExhausted` if the program wants more input than given.
+    pub fn execute(&mut self, program: &mut Program, input: &[isize]) -> Result<Vec<isize>, TraceError> {
+        let results = execute_with(program, input, |p, next_input| self.step(p, next_input));
+        self.out.flush()?;
+        results
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Collect the full trace of a run in memory.
+pub fn trace(program: &mut Program, input: &[isize]) -> Result<Vec<TraceRecord>, InvalidInstruction> {
+    let mut records = vec![];
+    execute_with::<_, InvalidInstruction, _>(program, input, |p, next_input|{
+        let (state, record) = step_traced(p, next_input, records.len())?;
+        records.extend(record);
+        Ok(state)
+    })?;
+
+    Ok(records)
+}
+
+/// The index of the first record at which two traces differ, if any.
+pub fn first_divergence(a: &[TraceRecord], b: &[TraceRecord]) -> Option<usize> {
+    match a.iter().zip(b.iter()).position(|(x, y)| x != y) {
+        Some(i) => Some(i),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_records() {
+        let mut p = Program::parse("109,9,203,0,1002,9,3,9,99,0").unwrap();
+        let records = trace(&mut p, &[7]).unwrap();
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].base, Some((0, 9)));
+        assert_eq!(records[1].input, Some(7));
+        assert_eq!(records[1].write, Some(MemWrite { addr: 9, old: 0, new: 7 }));
+        assert_eq!(records[2].values, vec![7, 3, 9]);
+        assert_eq!(records[2].to_string(), "2 @ 4: mul [9], #3, [9] = 7, 3, 9 | [9] 7 -> 21");
+        assert_eq!(records[3].opcode, OpCode::Halt);
+
+        let mut p = Program::parse("109,9,203,0,1002,9,3,9,99,0").unwrap();
+        assert_eq!(trace(&mut p, &[]), Err(InvalidInstruction::InputExhausted { ip: 2 }));
+    }
+
+    #[test]
+    fn tracer_formats() {
+        let mut p = Program::parse("104,12,99").unwrap();
+        let mut tracer = Tracer::new(vec![], TraceFormat::JsonLines);
+
+        assert_eq!(tracer.execute(&mut p, &[]).unwrap(), vec![12]);
+        assert_eq!(tracer.steps(), 2);
+
+        let mut p = Program::parse("3,0,99").unwrap();
+        assert!(matches!(tracer.execute(&mut p, &[]), Err(TraceError::Program(InvalidInstruction::InputExhausted { ip: 0 }))));
+
+        let text = String::from_utf8(tracer.into_inner()).unwrap();
+        assert_eq!(text.lines().next(), Some(concat!(
+            "{\"step\":0,\"ip\":0,\"opcode\":\"out\",\"modes\":[1],\"args\":[12],\"values\":[12],",
+            "\"write\":null,\"base\":null,\"input\":null,\"output\":12}"
+        )));
+    }
+
+    #[test]
+    fn divergence() {
+        let source = "3,9,8,9,10,9,4,9,99,-1,8";
+        let a = trace(&mut Program::parse(source).unwrap(), &[8]).unwrap();
+        let b = trace(&mut Program::parse(source).unwrap(), &[5]).unwrap();
+
+        assert_eq!(first_divergence(&a, &a), None);
+        assert_eq!(first_divergence(&a, &b), Some(0));
+        assert_eq!(first_divergence(&a[1..], &b[1..]), Some(0));
+        assert_eq!(first_divergence(&a, &a[..2]), Some(2));
+    }
+}
@@ -4,7 +4,8 @@ extern crate num_derive;
 extern crate lazy_static;
 
 #[allow(dead_code)]
-mod intcode;
+pub mod intcode;
+pub mod ocr;
 
 #[allow(dead_code)]
 mod day1;
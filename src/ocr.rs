@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+pub type Point = (isize, isize);
+
+// Capital letters in the block font puzzle output is drawn in, trimmed to
+// their lit columns. All are 6 rows high.
+const GLYPHS : &[(char, [&str; 6])] = &[
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#.."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"])
+];
+
+/// Glyphs that didn't match any known letter, as their position among the
+/// glyphs read and the rows they were drawn with (`#` lit, `.` not).
+#[derive(Debug,Clone,PartialEq)]
+pub struct OcrError {
+    pub unrecognized: Vec<(usize, Vec<String>)>
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for OcrError {}
+
+/// A picture made of lit and unlit pixels.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Grid {
+    width:  usize,
+    height: usize,
+    pixels: Vec<bool>
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize, pixels: Vec<bool>) -> Grid {
+        assert_eq!(pixels.len(), width * height, "pixel count doesn't match size");
+        Grid { width, height, pixels }
+    }
+
+    /// A decoded image layer, where pixels equal to 1 are lit. None if the
+    /// layer can't be split into rows of `width` pixels.
+    pub fn from_layer(layer: &[usize], width: usize) -> Option<Grid> {
+        if width == 0 || !layer.len().is_multiple_of(width) {
+            return None;
+        }
+
+        let pixels = layer.iter().map(|&v| v == 1).collect();
+        Some(Grid::new(width, layer.len() / width, pixels))
+    }
+
+    /// The bounding box of a sparse picture, where points equal to 1 are lit.
+    pub fn from_points(points: &HashMap<Point, isize>) -> Grid {
+        if points.is_empty() {
+            return Grid::new(0, 0, vec![]);
+        }
+
+        let min_x = points.keys().map(|p| p.0).min().unwrap();
+        let max_x = points.keys().map(|p| p.0).max().unwrap();
+        let min_y = points.keys().map(|p| p.1).min().unwrap();
+        let max_y = points.keys().map(|p| p.1).max().unwrap();
+
+        let pixels = (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+            .map(|p| points.get(&p) == Some(&1))
+            .collect();
+        Grid::new((max_x - min_x + 1) as usize, (max_y - min_y + 1) as usize, pixels)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    /// Split the picture into glyphs: runs of columns with something lit,
+    /// trimmed to the rows that have anything lit at all.
+    pub fn glyphs(&self) -> Vec<Vec<String>> {
+        let lit_rows : Vec<usize> = (0..self.height).filter(|&y| (0..self.width).any(|x| self.get(x, y))).collect();
+        let rows = match (lit_rows.first(), lit_rows.last()) {
+            (Some(&top), Some(&bottom)) => top..(bottom + 1),
+            _ => return vec![]
+        };
+
+        let mut glyphs = vec![];
+        let mut start = None;
+        for x in 0..=self.width {
+            let blank = !rows.clone().any(|y| self.get(x, y));
+            match (start, blank) {
+                (None, false) => start = Some(x),
+                (Some(from), true) => {
+                    glyphs.push(rows.clone().map(|y| (from..x).map(|x| if self.get(x, y) { '#' } else { '.' }).collect()).collect());
+                    start = None;
+                },
+                _ => {}
+            };
+        }
+
+        glyphs
+    }
+
+    /// Read the letters drawn in the picture.
+    pub fn read(&self) -> Result<String, OcrError> {
+        let mut text = String::new();
+        let mut unrecognized = vec![];
+
+        for (i, glyph) in self.glyphs().into_iter().enumerate() {
+            match GLYPHS.iter().find(|(_, rows)| rows.iter().eq(glyph.iter())) {
+                Some(&(letter, _)) => text.push(letter),
+                None => unrecognized.push((i, glyph))
+            };
+        }
+
+        if unrecognized.is_empty() {
+            Ok(text)
+        } else {
+            Err(OcrError { unrecognized })
+        }
+    }
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                write!(f, "{}", if self.get(x, y) { '#' } else { ' ' })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(rows: &[&str]) -> Grid {
+        let pixels = rows.iter().flat_map(|row| row.chars().map(|c| c == '#')).collect();
+        Grid::new(rows[0].len(), rows.len(), pixels)
+    }
+
+    #[test]
+    fn read_letters() {
+        let grid = parse(&[
+            "............",
+            ".###...#...#",
+            "..#....#...#",
+            "..#.....#.#.",
+            "..#......#..",
+            "..#......#..",
+            ".###.....#.."
+        ]);
+
+        assert_eq!(grid.glyphs().len(), 2);
+        assert_eq!(grid.read(), Ok("IY".to_string()));
+    }
+
+    #[test]
+    fn sources_and_errors() {
+        let layer = vec![
+            1, 1, 1, 1, 0, 1,
+            1, 0, 0, 0, 0, 1,
+            1, 1, 1, 0, 0, 1,
+            1, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, 0, 1,
+            1, 0, 0, 0, 0, 0
+        ];
+        let grid = Grid::from_layer(&layer, 6).unwrap();
+        assert_eq!(grid.height(), 6);
+        assert_eq!(grid.read(), Err(OcrError {
+            unrecognized: vec![(1, vec!["#", "#", "#", ".", "#", "."].into_iter().map(String::from).collect())]
+        }));
+
+        let points : HashMap<Point, isize> = (0..layer.len())
+            .map(|i| (((i % 6) as isize - 3, (i / 6) as isize + 10), layer[i] as isize))
+            .collect();
+        assert_eq!(Grid::from_points(&points), grid);
+
+        assert_eq!(Grid::from_layer(&layer, 0), None);
+        assert_eq!(Grid::from_layer(&layer, 5), None);
+    }
+}